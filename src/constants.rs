@@ -0,0 +1,53 @@
+/// A named constant that can be used in place of a number.
+#[derive(Clone, Copy, Debug)]
+pub struct Constant {
+    pub name: &'static str,
+    pub value: f64,
+}
+
+const MATH: &[Constant] = &[
+    // Euler's number
+    Constant {
+        name: "e",
+        value: std::f64::consts::E,
+    },
+];
+
+const PHYSICS: &[Constant] = &[
+    // Speed of light in vacuum (m/s)
+    Constant {
+        name: "c",
+        value: 299_792_458.0,
+    },
+    // Planck constant (J s)
+    Constant {
+        name: "h",
+        value: 6.626_070_15e-34,
+    },
+    // Newtonian constant of gravitation (m^3 kg^-1 s^-2)
+    Constant {
+        name: "G",
+        value: 6.674_30e-11,
+    },
+    // Boltzmann constant (J/K)
+    Constant {
+        name: "k_B",
+        value: 1.380_649e-23,
+    },
+];
+
+/// The physical constants enabled by `--constants physics`, in SI units.
+pub fn physics_constants() -> &'static [Constant] {
+    PHYSICS
+}
+
+/// Looks `name` up in `constants`, falling back to the mathematical
+/// constants that are always available. An enabled table can therefore
+/// shadow a mathematical constant of the same name.
+pub fn lookup(constants: &[Constant], name: &str) -> Option<f64> {
+    constants
+        .iter()
+        .chain(MATH)
+        .find(|c| c.name == name)
+        .map(|c| c.value)
+}
@@ -0,0 +1,466 @@
+/// The decimal and digit-grouping characters used by a locale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Locale {
+    pub decimal: char,
+    pub grouping: char,
+}
+
+const EN: Locale = Locale {
+    decimal: '.',
+    grouping: ',',
+};
+
+const LOCALES: &[(&str, Locale)] = &[
+    ("en", EN),
+    (
+        "de",
+        Locale {
+            decimal: ',',
+            grouping: '.',
+        },
+    ),
+    (
+        "fr",
+        Locale {
+            decimal: ',',
+            grouping: ' ',
+        },
+    ),
+];
+
+impl Locale {
+    pub fn from_name(name: &str) -> Option<Self> {
+        LOCALES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, locale)| *locale)
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        LOCALES.iter().map(|(n, _)| *n)
+    }
+}
+
+/// Settings that control how results are printed. They never affect the
+/// value that was computed.
+#[derive(Clone, Debug, Default)]
+pub struct FormatOptions {
+    /// Formats results with the locale's decimal and grouping characters.
+    /// Without a locale, results use Rust's plain `f64` formatting.
+    pub locale: Option<Locale>,
+    /// Printed in place of `NaN`.
+    pub nan_text: Option<String>,
+    /// Printed in place of `inf`, after a `-` for negative infinity.
+    pub inf_text: Option<String>,
+    /// Rounds results to two decimal places and prints them as an amount of
+    /// money, grouped using the locale (or `en` if none is set).
+    pub currency: Option<Currency>,
+    /// Echoes each console expression, consistently spaced, before its
+    /// result, as in `2 + 3 * 4 = 14`.
+    pub pretty: bool,
+    /// Prints whole-number results below a trillion in English words, such
+    /// as `negative forty-two`. Other results are printed as digits.
+    pub words: bool,
+    /// Prints exactly this many decimal places, padding with zeros, so 2.5
+    /// prints as `2.50` with two places. With `currency`, this replaces
+    /// the usual two places.
+    pub fixed: Option<usize>,
+    /// Rounds results to this many significant figures, so 1234.5 prints as
+    /// `1230` and 0.0012345 as `0.00123` with three.
+    pub sigfigs: Option<usize>,
+    /// Prints finite results as a continued fraction, as in `[3; 4, 12, 4]`.
+    pub cfrac: Option<ContinuedFraction>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Currency {
+    pub symbol: String,
+    pub rounding: Rounding,
+}
+
+/// Limits on a continued-fraction expansion.
+#[derive(Clone, Copy, Debug)]
+pub struct ContinuedFraction {
+    /// The most terms to print, including the integer part.
+    pub terms: usize,
+    /// How close to an integer a term must be to end the expansion there.
+    pub tolerance: f64,
+}
+
+/// How a value exactly halfway between two cents is rounded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    /// Away from zero, so 0.125 rounds to 0.13.
+    HalfUp,
+    /// To the nearest even cent (banker's rounding), so 0.125 rounds to 0.12.
+    HalfEven,
+}
+
+pub fn format_result(n: f64, opts: &FormatOptions) -> String {
+    if n.is_nan() {
+        if let Some(text) = &opts.nan_text {
+            return text.clone();
+        }
+    }
+
+    if n.is_infinite() {
+        if let Some(text) = &opts.inf_text {
+            let sign = if n < 0.0 { "-" } else { "" };
+            return format!("{}{}", sign, text);
+        }
+    }
+
+    if let Some(cfrac) = opts.cfrac {
+        if n.is_finite() {
+            return continued_fraction(n, cfrac);
+        }
+    }
+
+    if let Some(currency) = &opts.currency {
+        if n.is_finite() {
+            let places = opts.fixed.unwrap_or(2);
+            return format_currency(n, currency, places, opts.locale.unwrap_or(EN));
+        }
+    }
+
+    if opts.words && n.fract() == 0.0 && n.abs() < 1e12 {
+        return to_words(n as i64);
+    }
+
+    let plain = match (opts.fixed, opts.sigfigs) {
+        (Some(places), _) => format!("{:.*}", places, n),
+        (None, Some(figs)) if n.is_finite() => significant_figures(n, figs),
+        _ => n.to_string(),
+    };
+
+    match opts.locale {
+        Some(locale) if n.is_finite() => localize(&plain, locale),
+        _ => plain,
+    }
+}
+
+fn format_currency(n: f64, currency: &Currency, places: usize, locale: Locale) -> String {
+    let scale = 10f64.powi(places as i32);
+    let units = match currency.rounding {
+        Rounding::HalfUp => (n * scale).round(),
+        Rounding::HalfEven => (n * scale).round_ties_even(),
+    };
+    let amount = localize(&format!("{:.*}", places, units.abs() / scale), locale);
+    let sign = if units < 0.0 { "-" } else { "" };
+
+    format!("{}{}{}", sign, currency.symbol, amount)
+}
+
+/// Writes `n` in positional notation rounded to `figs` significant figures.
+/// Rounding is done by the scientific formatter, so a value like 9.996 that
+/// rounds up to the next power of ten gets the right number of places.
+fn significant_figures(n: f64, figs: usize) -> String {
+    let scientific = format!("{:.*e}", figs - 1, n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i64 = exponent.parse().unwrap();
+
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+    // The number of digits before the decimal point.
+    let point = exponent + 1;
+
+    let unsigned = if point <= 0 {
+        format!("0.{}{}", "0".repeat(-point as usize), digits)
+    } else if point as usize >= digits.len() {
+        format!("{}{}", digits, "0".repeat(point as usize - digits.len()))
+    } else {
+        let (integer, fraction) = digits.split_at(point as usize);
+        format!("{}.{}", integer, fraction)
+    };
+
+    format!("{}{}", sign, unsigned)
+}
+
+/// Expands `n` into a continued fraction, stopping once a term is within
+/// the tolerance of an integer, the expansion is exact, or the term limit
+/// is reached.
+fn continued_fraction(n: f64, limits: ContinuedFraction) -> String {
+    let mut terms = Vec::new();
+    let mut x = n;
+
+    loop {
+        let nearest = x.round();
+        if (x - nearest).abs() < limits.tolerance {
+            terms.push(nearest);
+            break;
+        }
+
+        let term = x.floor();
+        terms.push(term);
+        if terms.len() == limits.terms || x == term {
+            break;
+        }
+        x = 1.0 / (x - term);
+    }
+
+    let rest: Vec<_> = terms[1..].iter().map(f64::to_string).collect();
+    if rest.is_empty() {
+        format!("[{}]", terms[0])
+    } else {
+        format!("[{}; {}]", terms[0], rest.join(", "))
+    }
+}
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const SCALES: [(i64, &str); 3] = [
+    (1_000_000_000, "billion"),
+    (1_000_000, "million"),
+    (1_000, "thousand"),
+];
+
+fn to_words(n: i64) -> String {
+    if n < 0 {
+        return format!("negative {}", to_words(-n));
+    }
+    if n == 0 {
+        return ONES[0].to_string();
+    }
+
+    let mut words = Vec::new();
+    let mut rest = n;
+
+    for (scale, name) in SCALES.iter() {
+        if rest >= *scale {
+            words.push(format!("{} {}", hundreds_to_words(rest / scale), name));
+            rest %= scale;
+        }
+    }
+
+    if rest > 0 {
+        words.push(hundreds_to_words(rest));
+    }
+
+    words.join(" ")
+}
+
+/// Spells out a number from 1 to 999.
+fn hundreds_to_words(n: i64) -> String {
+    let mut words = Vec::new();
+
+    if n >= 100 {
+        words.push(format!("{} hundred", ONES[(n / 100) as usize]));
+    }
+
+    match (n % 100) as usize {
+        0 => {}
+        rest @ 1..=19 => words.push(ONES[rest].to_string()),
+        rest if rest % 10 == 0 => words.push(TENS[rest / 10].to_string()),
+        rest => words.push(format!("{}-{}", TENS[rest / 10], ONES[rest % 10])),
+    }
+
+    words.join(" ")
+}
+
+/// Rewrites a plainly formatted number with the locale's separators.
+fn localize(plain: &str, locale: Locale) -> String {
+    let (sign, digits) = match plain.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", plain),
+    };
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+
+    let mut out = String::from(sign);
+    out.push_str(&group(integer, locale.grouping));
+
+    if let Some(fraction) = fraction {
+        out.push(locale.decimal);
+        out.push_str(fraction);
+    }
+
+    out
+}
+
+/// Inserts `separator` between each group of three digits.
+fn group(integer: &str, separator: char) -> String {
+    let mut out = String::new();
+
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i).is_multiple_of(3) {
+            out.push(separator);
+        }
+        out.push(digit);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_locale(name: &str) -> FormatOptions {
+        FormatOptions {
+            locale: Locale::from_name(name),
+            ..FormatOptions::default()
+        }
+    }
+
+    fn currency(rounding: Rounding) -> FormatOptions {
+        FormatOptions {
+            currency: Some(Currency {
+                symbol: "$".to_string(),
+                rounding,
+            }),
+            ..FormatOptions::default()
+        }
+    }
+
+    #[test]
+    fn plain_by_default() {
+        let opts = FormatOptions::default();
+        assert_eq!(format_result(1234.5, &opts), "1234.5");
+        assert_eq!(format_result(2.0, &opts), "2");
+        assert_eq!(format_result(f64::NAN, &opts), "NaN");
+        assert_eq!(format_result(1.0 / 0.0, &opts), "inf");
+    }
+
+    #[test]
+    fn locales() {
+        assert_eq!(format_result(1234.56, &with_locale("en")), "1,234.56");
+        assert_eq!(format_result(1234.56, &with_locale("de")), "1.234,56");
+        assert_eq!(format_result(-1234567.0, &with_locale("fr")), "-1 234 567");
+        assert_eq!(format_result(123.0, &with_locale("de")), "123");
+        assert!(Locale::from_name("xx").is_none());
+    }
+
+    #[test]
+    fn nan_and_infinity_text() {
+        let opts = FormatOptions {
+            nan_text: Some("undefined".to_string()),
+            inf_text: Some("∞".to_string()),
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_result(1.0 / 0.0, &opts), "∞");
+        assert_eq!(format_result(-1.0 / 0.0, &opts), "-∞");
+        assert_eq!(format_result(f64::NAN, &opts), "undefined");
+        assert_eq!(format_result(1.5, &opts), "1.5");
+    }
+
+    #[test]
+    fn currency_amounts() {
+        let opts = currency(Rounding::HalfUp);
+        assert_eq!(format_result(1234.567, &opts), "$1,234.57");
+        assert_eq!(format_result(-1234.567, &opts), "-$1,234.57");
+        assert_eq!(format_result(0.125, &opts), "$0.13");
+        assert_eq!(format_result(-0.001, &opts), "$0.00");
+        assert_eq!(format_result(0.125, &currency(Rounding::HalfEven)), "$0.12");
+
+        let opts = FormatOptions {
+            fixed: Some(0),
+            locale: Locale::from_name("de"),
+            ..opts
+        };
+        assert_eq!(format_result(1234.567, &opts), "$1.235");
+    }
+
+    #[test]
+    fn words() {
+        let opts = FormatOptions {
+            words: true,
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_result(0.0, &opts), "zero");
+        assert_eq!(format_result(-5.0, &opts), "negative five");
+        assert_eq!(format_result(1000.0, &opts), "one thousand");
+        assert_eq!(format_result(1_000_000.0, &opts), "one million");
+        assert_eq!(
+            format_result(1234.0, &opts),
+            "one thousand two hundred thirty-four"
+        );
+        assert_eq!(format_result(1.5, &opts), "1.5");
+        assert_eq!(format_result(1e12, &opts), "1000000000000");
+        assert_eq!(
+            to_words(999_999_999_999),
+            "nine hundred ninety-nine billion nine hundred ninety-nine million \
+             nine hundred ninety-nine thousand nine hundred ninety-nine"
+        );
+    }
+
+    #[test]
+    fn fixed_decimal_places() {
+        let opts = FormatOptions {
+            fixed: Some(2),
+            ..FormatOptions::default()
+        };
+        assert_eq!(format_result(2.0, &opts), "2.00");
+        assert_eq!(format_result(2.5, &opts), "2.50");
+        assert_eq!(
+            format_result(
+                1234.5,
+                &FormatOptions {
+                    fixed: Some(2),
+                    ..with_locale("en")
+                }
+            ),
+            "1,234.50"
+        );
+    }
+
+    #[test]
+    fn significant_figures_across_magnitudes() {
+        assert_eq!(significant_figures(1234.5, 3), "1230");
+        assert_eq!(significant_figures(0.0012345, 3), "0.00123");
+        assert_eq!(significant_figures(9.996, 3), "10.0");
+        assert_eq!(significant_figures(-2.75, 1), "-3");
+        assert_eq!(significant_figures(0.0, 3), "0.00");
+        assert_eq!(significant_figures(123.0, 5), "123.00");
+    }
+
+    #[test]
+    fn continued_fractions() {
+        let limits = |terms| ContinuedFraction {
+            terms,
+            tolerance: 1e-9,
+        };
+        let golden_ratio = (1.0 + 5f64.sqrt()) / 2.0;
+
+        assert_eq!(continued_fraction(golden_ratio, limits(4)), "[1; 1, 1, 1]");
+        assert_eq!(continued_fraction(3.245, limits(10)), "[3; 4, 12, 4]");
+        assert_eq!(continued_fraction(2.0, limits(10)), "[2]");
+        assert_eq!(continued_fraction(-0.5, limits(10)), "[-1; 2]");
+
+        let exact = ContinuedFraction {
+            terms: 10,
+            tolerance: 0.0,
+        };
+        assert_eq!(continued_fraction(2.0, exact), "[2]");
+        assert_eq!(continued_fraction(2.5, exact), "[2; 2]");
+    }
+}
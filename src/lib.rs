@@ -0,0 +1,1585 @@
+//! An arithmetic expression evaluator. [`eval`] evaluates an expression
+//! with the default settings, [`eval_with`] takes an [`EvalContext`],
+//! [`solve_with_warnings`] also reports non-fatal [`Warning`]s, and
+//! [`solve_detailed`] classifies the result. [`solve_prefix`] evaluates an
+//! expression in prefix notation instead, and [`solve_steps`] returns each
+//! step of an evaluation.
+
+pub mod constants;
+
+use constants::Constant;
+use regex::{Captures, Match, Regex};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Returns an `EvalError` from the enclosing function unless `cond` holds.
+macro_rules! ensure {
+    ($cond:expr, $($message:tt)+) => {
+        // Written without `!` so that conditions comparing floats don't
+        // become negated partial comparisons.
+        if $cond {
+        } else {
+            return Err(EvalError::new(format!($($message)+)));
+        }
+    };
+}
+
+/// Evaluates `expr` with the default settings.
+pub fn eval(expr: &str) -> Result<f64, EvalError> {
+    eval_with(expr, &EvalContext::default())
+}
+
+/// Evaluates `expr` with the given settings, discarding any warnings.
+pub fn eval_with(expr: &str, ctx: &EvalContext) -> Result<f64, EvalError> {
+    solve_with_warnings(expr, ctx).0
+}
+
+/// Evaluates `expr` like [`eval_with`], also saying what kind of number the
+/// result is.
+pub fn solve_detailed(expr: &str, ctx: &EvalContext) -> Result<EvalResult, EvalError> {
+    eval_with(expr, ctx).map(|value| EvalResult {
+        value,
+        is_integer: value.is_finite() && value.fract() == 0.0,
+        is_finite: value.is_finite(),
+    })
+}
+
+/// A result along with its kind, so a caller can decide how to present it,
+/// for example only offering other bases for integers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EvalResult {
+    pub value: f64,
+    /// Whether the value is a whole number. Infinity and NaN aren't.
+    pub is_integer: bool,
+    /// Whether the value is neither infinite nor NaN.
+    pub is_finite: bool,
+}
+
+/// Why an expression couldn't be evaluated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalError {
+    message: String,
+}
+
+impl EvalError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for EvalError {}
+
+/// Settings that change how an expression is tokenized and evaluated.
+#[derive(Clone, Debug)]
+pub struct EvalContext {
+    /// Reads `^` as integer XOR instead of exponentiation, as in C-family
+    /// languages. Exponentiation is then only available as `**`, so `2^3`
+    /// evaluates to 1 rather than 8.
+    pub caret_xor: bool,
+    /// Which tokens are read as exponentiation. With `caret_xor`, `^` is
+    /// XOR regardless and this only controls whether `**` is available.
+    pub power: PowerToken,
+    /// Accepts thousands separators in number literals, so `1,234` is read
+    /// as 1234. Commas have no other meaning in an expression, so this only
+    /// changes how they are read inside numbers. Groups after the first must
+    /// have exactly three digits.
+    pub group_input: bool,
+    /// Prints the shunting-yard output and operator stacks to stderr after
+    /// each token is processed.
+    pub trace_stack: bool,
+    /// Appends any missing `)` at the end of the input, with a warning,
+    /// instead of rejecting it as mismatched.
+    pub auto_close: bool,
+    /// Rejects binary operators without whitespace on both sides, so `2+3`
+    /// is an error and `2 + 3` is required.
+    pub strict_whitespace: bool,
+    /// Rounds the result of every operation, rather than only the printed
+    /// result, to match calculators that work in fixed point.
+    pub rounding: Option<StepRounding>,
+    /// Operators rejected wherever they appear. Disabling `^` also disables
+    /// `**` when both mean exponentiation.
+    pub disabled_operators: Vec<Operator>,
+    /// Named constants recognised as identifiers. Any other identifier is
+    /// rejected.
+    pub constants: &'static [Constant],
+    /// The largest magnitude accepted for a number literal. By default any
+    /// finite literal is accepted.
+    pub max_literal: f64,
+    /// The largest magnitude accepted for the right-hand side of `^`. This
+    /// is checked before the power is computed.
+    pub max_exponent: f64,
+    /// The most values or pending operators the evaluator may hold at once,
+    /// which bounds its memory use on deeply nested input.
+    pub max_stack: usize,
+}
+
+impl Default for EvalContext {
+    fn default() -> Self {
+        Self {
+            caret_xor: false,
+            power: PowerToken::Both,
+            group_input: false,
+            trace_stack: false,
+            auto_close: false,
+            strict_whitespace: false,
+            rounding: None,
+            disabled_operators: Vec::new(),
+            constants: &[],
+            max_literal: f64::MAX,
+            max_exponent: 1024.0,
+            max_stack: 1000,
+        }
+    }
+}
+
+/// The tokens accepted for exponentiation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerToken {
+    /// Only `^`.
+    Caret,
+    /// Only `**`.
+    Stars,
+    Both,
+}
+
+impl PowerToken {
+    fn accepts(&self, symbol: &str) -> bool {
+        match self {
+            Self::Caret => symbol == "^",
+            Self::Stars => symbol == "**",
+            Self::Both => true,
+        }
+    }
+}
+
+impl EvalContext {
+    /// Applies the configured intermediate rounding, if any, to `n`.
+    fn round(&self, n: f64) -> f64 {
+        match self.rounding {
+            Some(rounding) if n.is_finite() => rounding.apply(n),
+            _ => n,
+        }
+    }
+}
+
+/// Rounding applied after each operation.
+#[derive(Clone, Copy, Debug)]
+pub struct StepRounding {
+    pub mode: RoundMode,
+    pub places: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoundMode {
+    Nearest,
+    TowardZero,
+    /// Toward positive infinity.
+    Up,
+    /// Toward negative infinity.
+    Down,
+}
+
+impl StepRounding {
+    /// Leaves `n` unchanged if it can't be scaled to the number of places,
+    /// since rounding it there wouldn't change it.
+    fn apply(&self, n: f64) -> f64 {
+        let scale = 10f64.powi(self.places);
+        let scaled = n * scale;
+        if scale == 0.0 || !scaled.is_finite() {
+            return n;
+        }
+
+        // Scaling is inexact, so 0.99 can become 99.00000000000001; a value
+        // that close to a whole number is taken as exact rather than being
+        // pushed to the next one by a directed mode.
+        let nearest = scaled.round();
+        let scaled = if (scaled - nearest).abs() <= 1e-9 * nearest.abs().max(1.0) {
+            nearest
+        } else {
+            scaled
+        };
+
+        let rounded = match self.mode {
+            RoundMode::Nearest => scaled.round(),
+            RoundMode::TowardZero => scaled.trunc(),
+            RoundMode::Up => scaled.ceil(),
+            RoundMode::Down => scaled.floor(),
+        };
+        rounded / scale
+    }
+}
+
+/// A non-fatal problem noticed while reading an expression. Warnings are
+/// kept even if the expression then fails to evaluate.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    /// A nonzero number literal too small for an `f64`, read as 0.
+    Underflow(String),
+    /// The number of `)` appended by `auto_close`.
+    AutoClosed(usize),
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Underflow(number) => {
+                write!(f, "{} is too small to represent and was read as 0", number)
+            }
+            Self::AutoClosed(count) => write!(
+                f,
+                "added {} missing closing parenthes{}",
+                count,
+                if *count == 1 { "is" } else { "es" }
+            ),
+        }
+    }
+}
+
+/// Time spent in each phase of evaluating an expression.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    pub tokenize: Duration,
+    pub evaluate: Duration,
+}
+
+impl Timings {
+    pub fn add(&mut self, other: Timings) {
+        self.tokenize += other.tokenize;
+        self.evaluate += other.evaluate;
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tokenize {:?}, evaluate {:?}",
+            self.tokenize, self.evaluate
+        )
+    }
+}
+
+/// Solves `str`, also returning any warnings, which are left to the caller
+/// to report.
+pub fn solve_with_warnings(str: &str, ctx: &EvalContext) -> (Result<f64, EvalError>, Vec<Warning>) {
+    let (value, warnings, _) = solve_timed(str, ctx);
+    (value, warnings)
+}
+
+/// Like [`solve_with_warnings`], also returning how long tokenizing and
+/// evaluating took. A phase that wasn't reached takes no time.
+pub fn solve_timed(
+    str: &str,
+    ctx: &EvalContext,
+) -> (Result<f64, EvalError>, Vec<Warning>, Timings) {
+    let mut warnings = Vec::new();
+    let mut timings = Timings::default();
+
+    let start = Instant::now();
+    let tokens = tokenize(str, ctx, &mut warnings);
+    timings.tokenize = start.elapsed();
+
+    let value = tokens.and_then(|tokens| {
+        let start = Instant::now();
+        let value = evaluate(&tokens, ctx, None);
+        timings.evaluate = start.elapsed();
+        value
+    });
+
+    (value, warnings, timings)
+}
+
+/// Evaluates an expression written in prefix (Polish) notation, such as
+/// `+ 2 * 3 4`, which is 14. Numbers, constants and operators are read as in
+/// an infix expression, but parentheses aren't needed and aren't allowed:
+/// each operator takes the next one or two complete expressions as its
+/// operands.
+pub fn solve_prefix(str: &str, ctx: &EvalContext) -> Result<f64, EvalError> {
+    let str = &replace_symbol_aliases(str);
+    let mut warnings = Vec::new();
+
+    let mut tokens = Vec::new();
+    for captures in captures(token_regex(ctx.group_input), str)? {
+        tokens.push(read_token(str, &captures, ctx, &mut warnings)?);
+    }
+
+    // Reading from the end, an operator's operands have already been
+    // evaluated, with its first operand on top of the stack.
+    let mut operands: Vec<f64> = Vec::new();
+    for token in tokens.into_iter().rev() {
+        match token {
+            Token::Number(n) => operands.push(n),
+            Token::Operator(Operator::LPar) | Token::Operator(Operator::RPar) => {
+                return Err(EvalError::new(
+                    "Parentheses aren't allowed in prefix notation",
+                ));
+            }
+            Token::Operator(op) => {
+                // Apply takes the second operand from the top.
+                let len = operands.len();
+                if !op.is_unary() && len >= 2 {
+                    operands.swap(len - 1, len - 2);
+                }
+                operands.apply(op, ctx)?;
+            }
+        }
+
+        ensure!(
+            operands.len() <= ctx.max_stack,
+            "Expression exceeds the stack limit of {}",
+            ctx.max_stack
+        );
+    }
+
+    match operands[..] {
+        [n] => Ok(n),
+        [] => Err(EvalError::new("Empty expression")),
+        _ => Err(EvalError::new(format!(
+            "Too many operands: {} expressions are left without an operator",
+            operands.len()
+        ))),
+    }
+}
+
+/// Evaluates `str` like [`eval_with`], instead returning the state of the
+/// evaluator after each token. This is the same state `trace_stack` prints.
+/// After the last step, the remaining operators are applied from the top of
+/// the stack down.
+pub fn solve_steps(str: &str, ctx: &EvalContext) -> Result<Vec<EvalStep>, EvalError> {
+    let tokens = tokenize(str, ctx, &mut Vec::new())?;
+    let mut steps = Vec::new();
+    evaluate(&tokens, ctx, Some(&mut steps))?;
+
+    Ok(steps)
+}
+
+/// The shunting-yard stacks after a token has been processed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalStep {
+    /// The token, as it is displayed rather than as it was written.
+    pub token: String,
+    /// The values computed so far, with the most recent last.
+    pub output: Vec<f64>,
+    /// The operators waiting to be applied, with the next one last.
+    pub operators: Vec<Operator>,
+}
+
+impl Display for EvalStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |items: Vec<String>| items.join(" ");
+
+        write!(
+            f,
+            "{:<6} output: [{}]  operators: [{}]",
+            self.token,
+            join(self.output.iter().map(f64::to_string).collect()),
+            join(self.operators.iter().map(Operator::to_string).collect())
+        )
+    }
+}
+
+/// Evaluates a token stream with the shunting-yard algorithm, recording the
+/// state after each token in `steps` if given.
+fn evaluate(
+    tokens: &[Token],
+    ctx: &EvalContext,
+    mut steps: Option<&mut Vec<EvalStep>>,
+) -> Result<f64, EvalError> {
+    let mut output: Vec<f64> = Vec::new();
+    let mut operator_stack: Vec<Operator> = Vec::new();
+
+    for &token in tokens {
+        match token {
+            Token::Number(n) => output.push(n),
+            Token::Operator(Operator::LPar) => operator_stack.push(Operator::LPar),
+            Token::Operator(Operator::RPar) => loop {
+                match operator_stack.pop() {
+                    Some(Operator::LPar) => break,
+                    Some(op) => output.apply(op, ctx)?,
+                    None => return Err(EvalError::new("Mismatched parentheses")),
+                }
+            },
+            Token::Operator(op) if op.is_unary() => operator_stack.push(op),
+            Token::Operator(op) => {
+                while let Some(stack_operator) = first_non_para_in_stack(&operator_stack) {
+                    if stack_operator.precedence() > op.precedence()
+                        || (stack_operator.precedence() == op.precedence()
+                            && op.is_left_associative())
+                    {
+                        output.apply(operator_stack.pop().unwrap(), ctx)?;
+                    } else {
+                        break;
+                    }
+                }
+
+                operator_stack.push(op);
+            }
+        }
+
+        ensure!(
+            output.len() <= ctx.max_stack && operator_stack.len() <= ctx.max_stack,
+            "Expression exceeds the stack limit of {}",
+            ctx.max_stack
+        );
+
+        if ctx.trace_stack || steps.is_some() {
+            let step = EvalStep {
+                token: token.to_string(),
+                output: output.clone(),
+                operators: operator_stack.clone(),
+            };
+            if ctx.trace_stack {
+                eprintln!("{}", step);
+            }
+            if let Some(steps) = steps.as_mut() {
+                steps.push(step);
+            }
+        }
+    }
+
+    operator_stack.reverse();
+
+    for operator in operator_stack {
+        ensure!(operator != Operator::LPar, "Mismatched parentheses");
+        output.apply(operator, ctx)?;
+    }
+
+    match output[..] {
+        [n] => Ok(n),
+        [] => Err(EvalError::new("Empty expression")),
+        _ => Err(EvalError::new("Missing operator between operands")),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Token {
+    Number(f64),
+    Operator(Operator),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Number(n) => n.fmt(f),
+            Self::Operator(op) => op.fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Exp,
+    Xor,
+    And,
+    Or,
+    Not,
+    LPar,
+    RPar,
+}
+
+impl Operator {
+    /// Every operator other than the parentheses.
+    const ALL: [Self; 11] = [
+        Self::Add,
+        Self::Sub,
+        Self::Mul,
+        Self::Div,
+        Self::FloorDiv,
+        Self::Mod,
+        Self::Exp,
+        Self::Xor,
+        Self::And,
+        Self::Or,
+        Self::Not,
+    ];
+
+    pub fn from_symbol(s: &str, ctx: &EvalContext) -> Option<Self> {
+        match s {
+            "+" => Some(Self::Add),
+            "-" => Some(Self::Sub),
+            "*" => Some(Self::Mul),
+            "/" => Some(Self::Div),
+            "//" => Some(Self::FloorDiv),
+            "%" => Some(Self::Mod),
+            "^" if ctx.caret_xor => Some(Self::Xor),
+            "^" | "**" => Some(Self::Exp),
+            "(" => Some(Self::LPar),
+            ")" => Some(Self::RPar),
+            _ => None,
+        }
+    }
+
+    pub fn from_keyword(s: &str) -> Option<Self> {
+        match s {
+            "and" => Some(Self::And),
+            "or" => Some(Self::Or),
+            "not" => Some(Self::Not),
+            _ if s.eq_ignore_ascii_case("mod") => Some(Self::Mod),
+            _ => None,
+        }
+    }
+
+    /// The symbols and keywords that read as this operator under `ctx`.
+    fn spellings(&self, ctx: &EvalContext) -> Vec<&'static str> {
+        match self {
+            Self::Exp => ["^", "**"]
+                .iter()
+                .copied()
+                .filter(|s| Self::from_symbol(s, ctx) == Some(Self::Exp) && ctx.power.accepts(s))
+                .collect(),
+            Self::Xor if ctx.caret_xor => vec!["^"],
+            Self::Xor => vec![],
+            Self::Mod => vec!["%", "mod"],
+            Self::And => vec!["and"],
+            Self::Or => vec!["or"],
+            Self::Not => vec!["not"],
+            Self::Add => vec!["+"],
+            Self::Sub => vec!["-"],
+            Self::Mul => vec!["*"],
+            Self::Div => vec!["/"],
+            Self::FloorDiv => vec!["//"],
+            Self::LPar => vec!["("],
+            Self::RPar => vec![")"],
+        }
+    }
+
+    fn precedence(&self) -> usize {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::Not => 3,
+            Self::Xor => 4,
+            Self::Add | Self::Sub => 5,
+            Self::Mul | Self::Div | Self::FloorDiv | Self::Mod => 6,
+            Self::Exp => 7,
+            _ => 0,
+        }
+    }
+
+    /// Whether this is a prefix operator taking a single operand.
+    fn is_unary(&self) -> bool {
+        matches!(self, Self::Not)
+    }
+
+    fn is_left_associative(&self) -> bool {
+        matches!(
+            self,
+            Self::Add
+                | Self::Sub
+                | Self::Mul
+                | Self::Div
+                | Self::FloorDiv
+                | Self::Mod
+                | Self::Xor
+                | Self::And
+                | Self::Or
+        )
+    }
+
+    fn apply(&self, lhs: f64, rhs: f64) -> Result<f64, EvalError> {
+        Ok(match self {
+            Self::Add => lhs + rhs,
+            Self::Sub => lhs - rhs,
+            Self::Mul => lhs * rhs,
+            Self::Div => lhs / rhs,
+            Self::FloorDiv => {
+                ensure!(rhs != 0.0, "Division by zero");
+                (lhs / rhs).floor()
+            }
+            Self::Mod => lhs % rhs,
+            Self::Exp => lhs.powf(rhs),
+            Self::Xor => {
+                ensure!(
+                    lhs.fract() == 0.0 && rhs.fract() == 0.0,
+                    "XOR requires integer operands"
+                );
+                ((lhs as i64) ^ (rhs as i64)) as f64
+            }
+            // Any non-zero value is true. Both operands have already been
+            // evaluated, so there is no short-circuiting.
+            Self::And => bool_to_f64(lhs != 0.0 && rhs != 0.0),
+            Self::Or => bool_to_f64(lhs != 0.0 || rhs != 0.0),
+            _ => unreachable!("{} is not a binary operator", self),
+        })
+    }
+
+    fn apply_unary(&self, operand: f64) -> f64 {
+        match self {
+            Self::Not => bool_to_f64(operand == 0.0),
+            _ => unreachable!("{} is not a unary operator", self),
+        }
+    }
+}
+
+/// Lists the operators available under `ctx` from tightest to loosest
+/// binding, one precedence level per line, with each level's associativity.
+/// Operators are shown by the symbols and keywords that are written in an
+/// expression.
+pub fn precedence_table(ctx: &EvalContext) -> String {
+    let mut levels: Vec<usize> = Operator::ALL.iter().map(Operator::precedence).collect();
+    levels.sort_unstable_by(|a, b| b.cmp(a));
+    levels.dedup();
+
+    let mut table = String::new();
+
+    for level in levels {
+        let operators: Vec<Operator> = Operator::ALL
+            .iter()
+            .copied()
+            .filter(|op| op.precedence() == level && !ctx.disabled_operators.contains(op))
+            .collect();
+        let symbols: Vec<&str> = operators.iter().flat_map(|op| op.spellings(ctx)).collect();
+        if symbols.is_empty() {
+            continue;
+        }
+
+        let associativity = if operators[0].is_unary() {
+            "prefix"
+        } else if operators[0].is_left_associative() {
+            "left"
+        } else {
+            "right"
+        };
+
+        table.push_str(&format!("{:<14}{}\n", symbols.join(" "), associativity));
+    }
+
+    table
+}
+
+fn bool_to_f64(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Add => write!(f, "+"),
+            Self::Sub => write!(f, "-"),
+            Self::Mul => write!(f, "*"),
+            Self::Div => write!(f, "/"),
+            Self::FloorDiv => write!(f, "//"),
+            Self::Mod => write!(f, "%"),
+            Self::Exp => write!(f, "^"),
+            Self::Xor => write!(f, "xor"),
+            Self::And => write!(f, "and"),
+            Self::Or => write!(f, "or"),
+            Self::Not => write!(f, "not"),
+            Self::LPar => write!(f, "("),
+            Self::RPar => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(
+    str: &str,
+    ctx: &EvalContext,
+    warnings: &mut Vec<Warning>,
+) -> Result<Vec<Token>, EvalError> {
+    let mut tokens = Vec::new();
+
+    let str = &replace_symbol_aliases(str);
+    let regex = token_regex(ctx.group_input);
+
+    for captures in captures(regex, str)? {
+        let token = read_token(str, &captures, ctx, warnings)?;
+
+        if let Token::Operator(op) = token {
+            // A unary plus is the identity, so it is simply dropped.
+            if op == Operator::Add && in_unary_position(tokens.last()) {
+                continue;
+            }
+            let binary = !op.is_unary() && !matches!(op, Operator::LPar | Operator::RPar);
+            if ctx.strict_whitespace && binary {
+                check_spacing(str, captures.get(0).unwrap())?;
+            }
+        }
+        tokens.push(token);
+    }
+
+    if ctx.auto_close {
+        let added = close_parentheses(&mut tokens);
+        if added > 0 {
+            warnings.push(Warning::AutoClosed(added));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads the token matched by `token` in `str`, checking it against the
+/// limits and disabled operators in `ctx`.
+fn read_token(
+    str: &str,
+    token: &Captures,
+    ctx: &EvalContext,
+    warnings: &mut Vec<Warning>,
+) -> Result<Token, EvalError> {
+    if let Some(degrees) = token.name("deg").map(|m| m.as_str()) {
+        // Digits straight after an angle are a component missing its unit,
+        // as in `30d15`.
+        let angle = token.get(0).unwrap();
+        ensure!(
+            !str[angle.end()..].starts_with(|c: char| c.is_ascii_digit()),
+            "Expected 'm' or 's' after the digits following '{}'",
+            angle.as_str()
+        );
+        let total = dms_to_degrees(
+            degrees,
+            token.name("min").map(|m| m.as_str()),
+            token.name("sec").map(|m| m.as_str()),
+            ctx,
+        )?;
+        Ok(Token::Number(check_literal(angle.as_str(), total, ctx)?))
+    } else if let Some(number) = token.name("number").map(|m| m.as_str()) {
+        // A leading-dot number straight after digits is the tail of a
+        // literal with two points, such as `1.2.3`.
+        let start = token.get(0).unwrap().start();
+        ensure!(
+            !(number.starts_with('.') && str[..start].ends_with(|c: char| c.is_ascii_digit())),
+            "Malformed number with more than one '.'"
+        );
+        let n = f64::from_str(&ungroup_digits(number)?).unwrap();
+        let n = check_literal(number, n, ctx)?;
+
+        let mantissa = number.split(['e', 'E']).next().unwrap();
+        if n == 0.0 && mantissa.contains(|c| matches!(c, '1'..='9')) {
+            warnings.push(Warning::Underflow(number.to_owned()));
+        }
+        Ok(Token::Number(n))
+    } else if let Some(operator) = token.name("operator").map(|m| m.as_str()) {
+        let op = Operator::from_symbol(operator, ctx)
+            .ok_or_else(|| EvalError::new(format!("Unknown operator '{}'", operator)))?;
+        ensure!(
+            op != Operator::Exp || ctx.power.accepts(operator),
+            "'{}' is not enabled for exponentiation",
+            operator
+        );
+        ensure!(
+            !ctx.disabled_operators.contains(&op),
+            "'{}' is disabled",
+            operator
+        );
+        Ok(Token::Operator(op))
+    } else {
+        let name = token.name("ident").unwrap().as_str();
+
+        if let Some(op) = Operator::from_keyword(name) {
+            ensure!(
+                !ctx.disabled_operators.contains(&op),
+                "'{}' is disabled",
+                name
+            );
+            return Ok(Token::Operator(op));
+        }
+
+        let value = constants::lookup(ctx.constants, name)
+            .ok_or_else(|| EvalError::new(format!("Unknown identifier '{}'", name)))?;
+        Ok(Token::Number(value))
+    }
+}
+
+/// Fails unless the binary operator matched by `op` has whitespace on both
+/// sides, reporting the column of the operator.
+fn check_spacing(str: &str, op: Match) -> Result<(), EvalError> {
+    let column = str[..op.start()].chars().count() + 1;
+    let spaced = |c: Option<char>| c.is_some_and(char::is_whitespace);
+
+    ensure!(
+        spaced(str[..op.start()].chars().next_back()),
+        "Expected a space before '{}' at column {}",
+        op.as_str(),
+        column
+    );
+    ensure!(
+        spaced(str[op.end()..].chars().next()),
+        "Expected a space after '{}' at column {}",
+        op.as_str(),
+        column
+    );
+    Ok(())
+}
+
+/// Appends a `)` for each `(` left open at the end of `tokens`, returning
+/// how many were added.
+fn close_parentheses(tokens: &mut Vec<Token>) -> usize {
+    let mut depth = 0usize;
+    for token in tokens.iter() {
+        match token {
+            Token::Operator(Operator::LPar) => depth += 1,
+            Token::Operator(Operator::RPar) => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    tokens.extend((0..depth).map(|_| Token::Operator(Operator::RPar)));
+    depth
+}
+
+/// Whether an operator following `previous` would be in prefix position,
+/// i.e. at the start of the input or after an operator or `(`.
+fn in_unary_position(previous: Option<&Token>) -> bool {
+    match previous {
+        None => true,
+        Some(Token::Number(_)) => false,
+        Some(Token::Operator(op)) => *op != Operator::RPar,
+    }
+}
+
+/// Re-spaces an expression consistently, with a single space between
+/// tokens except just inside parentheses. Tokens keep their source text.
+/// Fails on a character that can't begin a token.
+pub fn normalize(str: &str, ctx: &EvalContext) -> Result<String, EvalError> {
+    let mut normalized = String::new();
+
+    let str = &replace_symbol_aliases(str);
+    for token in captures(token_regex(ctx.group_input), str)? {
+        let token = token.get(0).unwrap().as_str();
+
+        if !normalized.is_empty() && !normalized.ends_with('(') && token != ")" {
+            normalized.push(' ');
+        }
+        normalized.push_str(token);
+    }
+
+    Ok(normalized)
+}
+
+/// Finds binary operators of different precedence used side by side within
+/// the same parentheses, as in `2 + 3 * 4`, where a reader has to know the
+/// precedence rules to tell which is applied first. Columns count characters
+/// from 1. Fails on a character that can't begin a token.
+pub fn precedence_notes(str: &str, ctx: &EvalContext) -> Result<Vec<String>, EvalError> {
+    let mut notes = Vec::new();
+
+    // The previous binary operator at each nesting depth, with its symbol
+    // and column.
+    let mut previous: Vec<Option<(Operator, &str, usize)>> = vec![None];
+    let mut after_operand = false;
+
+    let str = &replace_symbol_aliases(str);
+    for token in captures(token_regex(ctx.group_input), str)? {
+        let whole = token.get(0).unwrap();
+        let column = str[..whole.start()].chars().count() + 1;
+
+        let op = if let Some(op) = token
+            .name("operator")
+            .and_then(|m| Operator::from_symbol(m.as_str(), ctx))
+        {
+            op
+        } else if let Some(op) = token
+            .name("ident")
+            .and_then(|m| Operator::from_keyword(m.as_str()))
+        {
+            op
+        } else {
+            after_operand = true;
+            continue;
+        };
+
+        match op {
+            Operator::LPar => previous.push(None),
+            Operator::RPar if previous.len() > 1 => {
+                previous.pop();
+            }
+            Operator::RPar => {}
+            _ if op.is_unary() || !after_operand => {}
+            _ => {
+                let symbol = whole.as_str();
+                if let Some((prev, prev_symbol, prev_column)) = previous.last().copied().flatten() {
+                    if prev.precedence() != op.precedence() {
+                        let (tighter, looser) = if op.precedence() > prev.precedence() {
+                            ((symbol, column), (prev_symbol, prev_column))
+                        } else {
+                            ((prev_symbol, prev_column), (symbol, column))
+                        };
+                        notes.push(format!(
+                            "'{}' at column {} binds more tightly than '{}' at column {}; \
+                             consider adding parentheses",
+                            tighter.0, tighter.1, looser.0, looser.1
+                        ));
+                    }
+                }
+                *previous.last_mut().unwrap() = Some((op, symbol, column));
+            }
+        }
+        after_operand = op == Operator::RPar;
+    }
+
+    Ok(notes)
+}
+
+/// Rewrites typeset operator symbols to their ASCII forms: `×`, `·` and `⋅`
+/// become `*`, and `÷` becomes `/`. None of them can appear in a number, so
+/// the decimal point is unaffected.
+fn replace_symbol_aliases(str: &str) -> String {
+    str.chars()
+        .map(|c| match c {
+            '×' | '·' | '⋅' => '*',
+            '÷' => '/',
+            c => c,
+        })
+        .collect()
+}
+
+/// Returns the compiled tokenizer regex. Both variants are compiled once
+/// and shared, so tokenizing never recompiles them and is safe to do from
+/// several threads at once.
+///
+/// An `e` straight after a number's digits and followed by (optionally
+/// signed) digits is exponent notation, so `2e3` is 2000. Anywhere else it
+/// is an identifier, and resolves to Euler's number unless an enabled
+/// constant table defines `e`.
+///
+/// The integer part of a number may be left out, as in `.5`.
+fn token_regex(group_input: bool) -> &'static Regex {
+    static PLAIN: OnceLock<Regex> = OnceLock::new();
+    static GROUPED: OnceLock<Regex> = OnceLock::new();
+
+    let (cell, integer) = if group_input {
+        (&GROUPED, "[0-9]+(?:,[0-9]+)*")
+    } else {
+        (&PLAIN, "[0-9]+")
+    };
+
+    cell.get_or_init(|| {
+        Regex::new(&format!(
+            concat!(
+                r"(?P<deg>[0-9]+(?:\.[0-9]+)?)d(?:(?P<min>[0-9]+(?:\.[0-9]+)?)m)?(?:(?P<sec>[0-9]+(?:\.[0-9]+)?)s)?",
+                r"|(?P<number>(?:{}(?:\.[0-9]+)?|\.[0-9]+)(?:[eE][+\-]?[0-9]+)?)",
+                r"|(?P<operator>\*\*|//|[+\-*/%^\(\)])",
+                r"|(?P<ident>[A-Za-z_][A-Za-z0-9_]*)",
+            ),
+            integer
+        ))
+        .unwrap()
+    })
+}
+
+/// Returns every match of `regex` in `str`, failing on the first character
+/// that isn't whitespace and isn't part of a match.
+fn captures<'a>(regex: &Regex, str: &'a str) -> Result<Vec<Captures<'a>>, EvalError> {
+    let mut tokens = Vec::new();
+    let mut end = 0;
+
+    for token in regex.captures_iter(str) {
+        let whole = token.get(0).unwrap();
+        check_gap(str, end, whole.start())?;
+        end = whole.end();
+        tokens.push(token);
+    }
+
+    check_gap(str, end, str.len())?;
+    Ok(tokens)
+}
+
+/// Fails unless `str[start..end]` is all whitespace.
+fn check_gap(str: &str, start: usize, end: usize) -> Result<(), EvalError> {
+    match str[start..end]
+        .char_indices()
+        .find(|(_, c)| !c.is_whitespace())
+    {
+        Some((i, c)) => Err(EvalError::new(format!(
+            "Unexpected character '{}' at column {}",
+            c,
+            str[..start + i].chars().count() + 1
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Strips thousands separators from a number literal, failing if the groups
+/// are malformed (e.g. `1,23`).
+fn ungroup_digits(number: &str) -> Result<String, EvalError> {
+    let integer = number.split(['.', 'e', 'E']).next().unwrap();
+    let groups: Vec<&str> = integer.split(',').collect();
+
+    let well_formed =
+        groups.len() == 1 || (groups[0].len() <= 3 && groups[1..].iter().all(|g| g.len() == 3));
+    ensure!(well_formed, "Malformed digit grouping in {}", number);
+
+    Ok(number.replace(',', ""))
+}
+
+/// Fails if the value `n` read from `literal` overflowed an `f64` or is
+/// larger than `ctx` allows, and otherwise returns it.
+fn check_literal(literal: &str, n: f64, ctx: &EvalContext) -> Result<f64, EvalError> {
+    ensure!(
+        n.is_finite(),
+        "Number {} is too large to represent",
+        literal
+    );
+    ensure!(
+        n.abs() <= ctx.max_literal,
+        "Number {} exceeds the maximum magnitude of {}",
+        literal,
+        ctx.max_literal
+    );
+    Ok(n)
+}
+
+/// Converts a degree/minute/second angle literal such as `30d15m` into a
+/// total number of degrees, checking each component like any other number
+/// literal. The result is always in degrees, so it is only meaningful where
+/// the surrounding calculation expects degrees.
+fn dms_to_degrees(
+    degrees: &str,
+    minutes: Option<&str>,
+    seconds: Option<&str>,
+    ctx: &EvalContext,
+) -> Result<f64, EvalError> {
+    let part = |s: Option<&str>| match s {
+        Some(s) => check_literal(s, f64::from_str(s).unwrap(), ctx),
+        None => Ok(0.0),
+    };
+
+    Ok(part(Some(degrees))? + part(minutes)? / 60.0 + part(seconds)? / 3600.0)
+}
+
+fn first_operator_in_stack(stack: &[Operator]) -> Option<Operator> {
+    if stack.is_empty() {
+        return None;
+    }
+
+    Some(stack[stack.len() - 1])
+}
+
+fn first_non_para_in_stack(stack: &[Operator]) -> Option<Operator> {
+    first_operator_in_stack(stack).and_then(|op| if op != Operator::LPar { Some(op) } else { None })
+}
+
+trait Apply {
+    fn apply(&mut self, op: Operator, ctx: &EvalContext) -> Result<(), EvalError>;
+}
+
+impl Apply for Vec<f64> {
+    fn apply(&mut self, op: Operator, ctx: &EvalContext) -> Result<(), EvalError> {
+        let missing = || EvalError::new(format!("Missing operand for {}", op));
+
+        if op.is_unary() {
+            let operand = self.pop().ok_or_else(missing)?;
+            self.push(ctx.round(op.apply_unary(operand)));
+            return Ok(());
+        }
+
+        let rhs = self.pop().ok_or_else(missing)?;
+        ensure!(
+            op != Operator::Exp || rhs.abs() <= ctx.max_exponent,
+            "Exponent {} exceeds the maximum of {}",
+            rhs,
+            ctx.max_exponent
+        );
+        let lhs = self.pop().ok_or_else(missing)?;
+        self.push(ctx.round(op.apply(lhs, rhs)?));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_error(expr: &str) -> String {
+        eval_error_with(expr, &EvalContext::default())
+    }
+
+    fn eval_error_with(expr: &str, ctx: &EvalContext) -> String {
+        eval_with(expr, ctx).unwrap_err().message().to_string()
+    }
+
+    #[test]
+    fn detailed_results() {
+        let ctx = EvalContext::default();
+        let detailed = |expr| solve_detailed(expr, &ctx).unwrap();
+
+        let integer = detailed("6 / 2");
+        assert_eq!(integer.value, 3.0);
+        assert!(integer.is_integer && integer.is_finite);
+
+        let fraction = detailed("1 / 4");
+        assert_eq!(fraction.value, 0.25);
+        assert!(!fraction.is_integer && fraction.is_finite);
+
+        let infinite = detailed("1 / 0");
+        assert_eq!(infinite.value, f64::INFINITY);
+        assert!(!infinite.is_integer && !infinite.is_finite);
+
+        assert!(!detailed("0 / 0").is_finite);
+        assert!(solve_detailed("1 +", &ctx).is_err());
+    }
+
+    #[test]
+    fn prefix_notation() {
+        let ctx = EvalContext::default();
+        let prefix = |expr| solve_prefix(expr, &ctx);
+
+        assert_eq!(prefix("+ 2 * 3 4"), Ok(14.0));
+        assert_eq!(prefix("* + 1 2 - 7 4"), Ok(9.0));
+        assert_eq!(prefix("- 10 / 6 2"), Ok(7.0));
+        assert_eq!(prefix("^ 2 ^ 3 2"), Ok(512.0));
+        assert_eq!(prefix("and not 0 1"), Ok(1.0));
+        assert_eq!(prefix("7"), Ok(7.0));
+
+        assert_eq!(
+            prefix("+ 2").unwrap_err().message(),
+            "Missing operand for +"
+        );
+        assert_eq!(
+            prefix("+ 1 2 3").unwrap_err().message(),
+            "Too many operands: 2 expressions are left without an operator"
+        );
+        assert!(prefix("+ (1) 2").is_err());
+        assert!(prefix("").is_err());
+        assert!(prefix("+ 1 2 !").is_err());
+    }
+
+    #[test]
+    fn evaluation_steps() {
+        use Operator::{Add, Mul};
+
+        let step = |token: &str, output: &[f64], operators: &[Operator]| EvalStep {
+            token: token.to_string(),
+            output: output.to_vec(),
+            operators: operators.to_vec(),
+        };
+
+        assert_eq!(
+            solve_steps("3 + 4 * 2", &EvalContext::default()),
+            Ok(vec![
+                step("3", &[3.0], &[]),
+                step("+", &[3.0], &[Add]),
+                step("4", &[3.0, 4.0], &[Add]),
+                step("*", &[3.0, 4.0], &[Add, Mul]),
+                step("2", &[3.0, 4.0, 2.0], &[Add, Mul]),
+            ])
+        );
+        assert_eq!(
+            step("*", &[3.0, 4.0], &[Add, Mul]).to_string(),
+            "*      output: [3 4]  operators: [+ *]"
+        );
+        assert!(solve_steps("3 +", &EvalContext::default()).is_err());
+    }
+
+    #[test]
+    fn exponent_notation_and_euler() {
+        assert_eq!(eval("2e3"), Ok(2000.0));
+        assert_eq!(eval("2*e"), Ok(2.0 * std::f64::consts::E));
+        assert_eq!(eval_error("2e"), "Missing operator between operands");
+
+        let ctx = EvalContext {
+            constants: constants::physics_constants(),
+            ..EvalContext::default()
+        };
+        assert!(eval_with("2c", &ctx).is_err());
+    }
+
+    #[test]
+    fn literal_and_exponent_limits() {
+        assert_eq!(eval("1e16"), Ok(1e16));
+        assert_eq!(eval("6.022e23"), Ok(6.022e23));
+        assert_eq!(eval("9007199254740992"), Ok(9007199254740992.0));
+
+        let start = Instant::now();
+        assert_eq!(
+            eval_error("2 ^ 100000"),
+            "Exponent 100000 exceeds the maximum of 1024"
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let ctx = EvalContext {
+            max_literal: 100.0,
+            ..EvalContext::default()
+        };
+        assert!(eval_with("1000", &ctx).is_err());
+        assert!(eval_with("1000d", &ctx).is_err());
+        assert!(eval_with("1d1000m", &ctx).is_err());
+        assert_eq!(eval_with("90d30m", &ctx), Ok(90.5));
+    }
+
+    #[test]
+    fn degree_minute_second_angles() {
+        assert_eq!(eval("30d30m"), Ok(30.5));
+        assert!((eval("1d0m36s").unwrap() - 1.01).abs() < 1e-12);
+        assert_eq!(
+            eval_error("30d15"),
+            "Expected 'm' or 's' after the digits following '30d'"
+        );
+    }
+
+    #[test]
+    fn digit_grouping() {
+        assert_eq!(ungroup_digits("1,234,567"), Ok("1234567".to_string()));
+        assert_eq!(ungroup_digits("1,234.5"), Ok("1234.5".to_string()));
+        assert_eq!(ungroup_digits("1,234e3"), Ok("1234e3".to_string()));
+        assert!(ungroup_digits("1,23").is_err());
+        assert!(ungroup_digits("1234,567").is_err());
+
+        let ctx = EvalContext {
+            group_input: true,
+            ..EvalContext::default()
+        };
+        assert_eq!(eval_with("1,234e3 + 1", &ctx), Ok(1234001.0));
+        assert!(eval("1,234").is_err());
+    }
+
+    #[test]
+    fn precedence_table_lists_source_symbols() {
+        let table = precedence_table(&EvalContext::default());
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows[0], "^ **          right");
+        assert_eq!(rows[1], "* / // % mod  left");
+        assert_eq!(rows[2], "+ -           left");
+        assert!(!table.contains("xor"));
+
+        let ctx = EvalContext {
+            caret_xor: true,
+            ..EvalContext::default()
+        };
+        let table = precedence_table(&ctx);
+        assert!(table.starts_with("**            right\n"));
+        assert!(table.contains("\n^             left\n"));
+    }
+
+    #[test]
+    fn warnings_are_returned_with_the_result() {
+        let (value, warnings) = solve_with_warnings("1e-400 + 2", &EvalContext::default());
+        assert_eq!(value, Ok(2.0));
+        assert_eq!(warnings, vec![Warning::Underflow("1e-400".to_string())]);
+
+        let (value, warnings) = solve_with_warnings("1e-400 +", &EvalContext::default());
+        assert!(value.is_err());
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        assert_eq!(eval_error("3!"), "Unexpected character '!' at column 2");
+        assert_eq!(
+            eval_error("2 + 3 @"),
+            "Unexpected character '@' at column 7"
+        );
+        assert_eq!(eval_error("√16"), "Unexpected character '√' at column 1");
+        assert_eq!(eval_error("$5 + 1"), "Unexpected character '$' at column 1");
+        assert_eq!(
+            eval_error("2 + [3]"),
+            "Unexpected character '[' at column 5"
+        );
+        assert_eq!(eval(" \t2 × 3 "), Ok(6.0));
+    }
+
+    #[test]
+    fn leading_decimal_point() {
+        assert_eq!(eval(".5"), Ok(0.5));
+        assert_eq!(eval("2 + .25"), Ok(2.25));
+        assert_eq!(eval_error("."), "Unexpected character '.' at column 1");
+        assert_eq!(eval_error("2."), "Unexpected character '.' at column 2");
+        assert_eq!(
+            eval_error("1.2.3"),
+            "Malformed number with more than one '.'"
+        );
+    }
+
+    #[test]
+    fn caret_as_xor() {
+        assert_eq!(eval("2 ^ 3"), Ok(8.0));
+
+        let ctx = EvalContext {
+            caret_xor: true,
+            ..EvalContext::default()
+        };
+        assert_eq!(eval_with("2 ^ 3", &ctx), Ok(1.0));
+        assert_eq!(eval_with("2 ** 3", &ctx), Ok(8.0));
+        assert_eq!(eval_with("1 + 2 ^ 3 * 2", &ctx), Ok(5.0));
+        assert!(eval_with("2.5 ^ 1", &ctx).is_err());
+    }
+
+    #[test]
+    fn evaluates_from_several_threads() {
+        let handles: Vec<_> = (0..8)
+            .map(|i| std::thread::spawn(move || eval(&format!("{} * 2 + 1", i))))
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), Ok(i as f64 * 2.0 + 1.0));
+        }
+    }
+
+    #[test]
+    fn physics_constants() {
+        let ctx = EvalContext {
+            constants: constants::physics_constants(),
+            ..EvalContext::default()
+        };
+        assert_eq!(eval_with("c", &ctx), Ok(299_792_458.0));
+        assert_eq!(eval_with("2 * k_B", &ctx), Ok(2.0 * 1.380_649e-23));
+        assert_eq!(eval_error("c"), "Unknown identifier 'c'");
+    }
+
+    #[test]
+    fn floor_division() {
+        assert_eq!(eval("7 // 2"), Ok(3.0));
+        assert_eq!(eval("(0 - 7) // 2"), Ok(-4.0));
+        assert_eq!(eval("2 * 7 // 2"), Ok(7.0));
+        assert_eq!(eval_error("1 // 0"), "Division by zero");
+        assert!(eval("7 / / 2").is_err());
+    }
+
+    #[test]
+    fn boolean_keywords() {
+        assert_eq!(eval("1 and 0"), Ok(0.0));
+        assert_eq!(eval("1 or 0"), Ok(1.0));
+        assert_eq!(eval("not 0"), Ok(1.0));
+        assert_eq!(eval("not 2 + 3"), Ok(0.0));
+        assert_eq!(eval("1 or 1 and 0"), Ok(1.0));
+        assert_eq!(eval("not 1 or 1"), Ok(1.0));
+    }
+
+    #[test]
+    fn mod_keyword() {
+        assert_eq!(eval("7 mod 3"), Ok(1.0));
+        assert_eq!(eval("7 % 3"), Ok(1.0));
+        assert_eq!(eval("7 MOD 3"), Ok(1.0));
+        assert_eq!(eval("2 + 7 mod 3"), Ok(3.0));
+        assert!(eval("mod").is_err());
+    }
+
+    #[test]
+    fn typeset_operator_symbols() {
+        assert_eq!(eval("3 · 4"), Ok(12.0));
+        assert_eq!(eval("3 ⋅ 4"), Ok(12.0));
+        assert_eq!(eval("3 × 4"), Ok(12.0));
+        assert_eq!(eval("1.5 ÷ 3"), Ok(0.5));
+    }
+
+    #[test]
+    fn overflowing_literals() {
+        assert_eq!(
+            eval_error("1e400"),
+            "Number 1e400 is too large to represent"
+        );
+
+        let (value, warnings) = solve_with_warnings("1e-400", &EvalContext::default());
+        assert_eq!(value, Ok(0.0));
+        assert_eq!(warnings, vec![Warning::Underflow("1e-400".to_string())]);
+
+        let (_, warnings) = solve_with_warnings("0e-400", &EvalContext::default());
+        assert!(warnings.is_empty());
+
+        let huge = "9".repeat(400);
+        assert_eq!(
+            eval_error(&format!("{}d", huge)),
+            format!("Number {} is too large to represent", huge)
+        );
+        assert!(eval(&format!("{0}d - {0}d", huge)).is_err());
+        assert!(eval(&format!("1d{}m", huge)).is_err());
+        assert!(eval(&format!("{}d{}m", f64::MAX, f64::MAX)).is_err());
+    }
+
+    #[test]
+    fn both_phases_are_timed() {
+        let (value, _, timings) = solve_timed("2 + 3 * 4", &EvalContext::default());
+        assert_eq!(value, Ok(14.0));
+        assert!(timings.tokenize > Duration::ZERO);
+        assert!(timings.evaluate > Duration::ZERO);
+
+        let (_, _, timings) = solve_timed("2 + x", &EvalContext::default());
+        assert_eq!(timings.evaluate, Duration::ZERO);
+    }
+
+    #[test]
+    fn unary_plus() {
+        assert_eq!(eval("+5"), Ok(5.0));
+        assert_eq!(eval("3 * +2"), Ok(6.0));
+        assert_eq!(eval("2 ^ +3"), Ok(8.0));
+        assert_eq!(eval("(+1) + +1"), Ok(2.0));
+    }
+
+    #[test]
+    fn parenthesis_errors() {
+        assert_eq!(eval_error(""), "Empty expression");
+        assert_eq!(eval_error("()"), "Empty expression");
+        assert_eq!(eval_error("2 * (3 + 4"), "Mismatched parentheses");
+        assert_eq!(eval_error("2 * 3)"), "Mismatched parentheses");
+    }
+
+    #[test]
+    fn auto_close() {
+        let ctx = EvalContext {
+            auto_close: true,
+            ..EvalContext::default()
+        };
+        let (value, warnings) = solve_with_warnings("2 * (3 + 4", &ctx);
+        assert_eq!(value, Ok(14.0));
+        assert_eq!(warnings, vec![Warning::AutoClosed(1)]);
+
+        let (value, warnings) = solve_with_warnings("((1 + 1", &ctx);
+        assert_eq!(value, Ok(2.0));
+        assert_eq!(warnings, vec![Warning::AutoClosed(2)]);
+        assert_eq!(
+            warnings[0].to_string(),
+            "added 2 missing closing parentheses"
+        );
+
+        assert!(solve_with_warnings("2 * (3 + 4)", &ctx).1.is_empty());
+    }
+
+    #[test]
+    fn disabled_operators() {
+        let ctx = EvalContext {
+            disabled_operators: vec![Operator::Exp],
+            ..EvalContext::default()
+        };
+        assert_eq!(eval_with("2 * 3", &ctx), Ok(6.0));
+        assert_eq!(eval_error_with("2 ^ 3", &ctx), "'^' is disabled");
+        assert_eq!(eval_error_with("2 ** 3", &ctx), "'**' is disabled");
+
+        let ctx = EvalContext {
+            disabled_operators: vec![Operator::Mod],
+            ..EvalContext::default()
+        };
+        assert_eq!(eval_error_with("7 mod 3", &ctx), "'mod' is disabled");
+        assert_eq!(eval_error_with("7 MOD 3", &ctx), "'MOD' is disabled");
+        assert_eq!(eval_error_with("7 % 3", &ctx), "'%' is disabled");
+    }
+
+    #[test]
+    fn rounding_every_operation() {
+        let round = |mode| EvalContext {
+            rounding: Some(StepRounding { mode, places: 2 }),
+            ..EvalContext::default()
+        };
+
+        assert_eq!(eval_with("1 / 3 * 3", &round(RoundMode::Nearest)), Ok(0.99));
+        assert_eq!(
+            eval_with("1 / 3 * 3", &round(RoundMode::TowardZero)),
+            Ok(0.99)
+        );
+        assert_eq!(eval_with("1 / 3 * 3", &round(RoundMode::Up)), Ok(1.02));
+        assert_eq!(eval_with("1 / 3 * 3", &round(RoundMode::Down)), Ok(0.99));
+        assert_eq!(eval("1 / 3 * 3"), Ok(1.0));
+    }
+
+    #[test]
+    fn step_rounding_modes() {
+        let apply = |mode, n| StepRounding { mode, places: 1 }.apply(n);
+
+        assert_eq!(apply(RoundMode::Nearest, -1.25), -1.3);
+        assert_eq!(apply(RoundMode::TowardZero, -1.29), -1.2);
+        assert_eq!(apply(RoundMode::Up, -1.21), -1.2);
+        assert_eq!(apply(RoundMode::Down, 1.29), 1.2);
+        assert_eq!(apply(RoundMode::Up, 0.3), 0.3);
+
+        let apply = |places, n| {
+            StepRounding {
+                mode: RoundMode::Nearest,
+                places,
+            }
+            .apply(n)
+        };
+        assert_eq!(apply(400, 2.0), 2.0);
+        assert_eq!(apply(300, 1e10 + 1.0), 1e10 + 1.0);
+        assert_eq!(apply(-400, 2.0), 2.0);
+    }
+
+    #[test]
+    fn stack_limit() {
+        let ctx = EvalContext {
+            max_stack: 10,
+            ..EvalContext::default()
+        };
+        let nested = vec!["1"; 20].join(" ^ ");
+        assert_eq!(
+            eval_error_with(&nested, &ctx),
+            "Expression exceeds the stack limit of 10"
+        );
+        assert_eq!(eval(&nested), Ok(1.0));
+        assert!(eval_with(&vec!["1"; 20].join(" + "), &ctx).is_ok());
+    }
+
+    #[test]
+    fn normalized_spacing() {
+        let ctx = EvalContext::default();
+        assert_eq!(normalize("2+  3*4", &ctx), Ok("2 + 3 * 4".to_string()));
+        assert_eq!(normalize("( 1+2 )*3", &ctx), Ok("(1 + 2) * 3".to_string()));
+        assert_eq!(normalize("7//2", &ctx), Ok("7 // 2".to_string()));
+        assert!(normalize("2 + 3 @", &ctx).is_err());
+    }
+
+    #[test]
+    fn notes_on_precedence() {
+        let ctx = EvalContext::default();
+        assert_eq!(
+            precedence_notes("2 + 3 * 4", &ctx),
+            Ok(vec![
+                "'*' at column 7 binds more tightly than '+' at column 3; \
+                 consider adding parentheses"
+                    .to_string()
+            ])
+        );
+        assert_eq!(precedence_notes("2 + (3 * 4)", &ctx), Ok(vec![]));
+        assert_eq!(precedence_notes("2 + 3 - 4", &ctx), Ok(vec![]));
+        assert!(precedence_notes("2 + 3!", &ctx).is_err());
+    }
+
+    #[test]
+    fn power_tokens() {
+        let power = |power| EvalContext {
+            power,
+            ..EvalContext::default()
+        };
+
+        assert_eq!(eval_with("2 ^ 10", &power(PowerToken::Caret)), Ok(1024.0));
+        assert!(eval_with("2 ** 10", &power(PowerToken::Caret)).is_err());
+        assert_eq!(eval_with("2 ** 10", &power(PowerToken::Stars)), Ok(1024.0));
+        assert_eq!(
+            eval_error_with("2 ^ 10", &power(PowerToken::Stars)),
+            "'^' is not enabled for exponentiation"
+        );
+        assert_eq!(eval_with("2 ^ 10", &power(PowerToken::Both)), Ok(1024.0));
+        assert_eq!(eval_with("2 ** 10", &power(PowerToken::Both)), Ok(1024.0));
+    }
+
+    #[test]
+    fn strict_whitespace() {
+        let ctx = EvalContext {
+            strict_whitespace: true,
+            ..EvalContext::default()
+        };
+        assert_eq!(eval_with("2 + 3", &ctx), Ok(5.0));
+        assert_eq!(eval_with("(2 + 3) * +4", &ctx), Ok(20.0));
+        assert_eq!(
+            eval_error_with("2+3", &ctx),
+            "Expected a space before '+' at column 2"
+        );
+        assert_eq!(
+            eval_error_with("2 +3", &ctx),
+            "Expected a space after '+' at column 3"
+        );
+        assert!(eval_with("(1)and 0", &ctx).is_err());
+    }
+}
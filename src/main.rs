@@ -1,208 +1,1020 @@
 #[macro_use]
 extern crate clap;
 
-use dialoguer::Input;
-use regex::Regex;
-use std::fmt::{self, Display};
-use std::str::FromStr;
+mod format;
+
+use calculator::constants;
+use calculator::{
+    normalize, precedence_notes, precedence_table, solve_timed, EvalContext, Operator, PowerToken,
+    RoundMode, StepRounding, Timings, Warning,
+};
+use clap::{Error, ErrorKind};
+use console::{Key, Term};
+use format::{format_result, ContinuedFraction, Currency, FormatOptions, Locale, Rounding};
+use regex::{NoExpand, Regex};
+use std::env;
+use std::io::{self, IsTerminal};
+use std::panic;
+use std::process;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
 
 fn main() {
     let matches = clap_app!(calculator =>
         (version: crate_version!())
         (author: crate_authors!())
         (about: crate_description!())
-        (@arg expr: "The expression to be evaluated")
+        (@arg expr: "The expression to be evaluated, defaulting to $CALC_EXPR if set")
+        (@arg caret_xor: --("caret-xor") "Treat ^ as bitwise XOR, using ** for exponentiation")
+        (@arg power: --power +takes_value possible_values(&["^", "**", "both"]) default_value("both") "Which tokens mean exponentiation; with --caret-xor only ** is available")
+        (@arg group_input: --("group-input") "Accept comma-grouped integers such as 1,234,567 in input")
+        (@arg trace_stack: --("trace-stack") "Print the output and operator stacks to stderr after each token")
+        (@arg constants: --constants +takes_value possible_value[physics] "Recognise a table of named constants")
+        (@arg locale: --locale +takes_value "Format results with a locale's decimal and grouping separators (en, de, fr)")
+        (@arg nan_text: --("nan-text") +takes_value "Text to print for a NaN result")
+        (@arg inf_text: --("inf-text") +takes_value "Text to print for an infinite result")
+        (@arg currency: --currency "Print results as money, rounded to two decimal places")
+        (@arg currency_symbol: --("currency-symbol") +takes_value default_value("$") "Symbol to print before amounts with --currency")
+        (@arg currency_rounding: --("currency-rounding") +takes_value possible_values(&["half-up", "half-even"]) default_value("half-up") "How --currency rounds amounts halfway between two cents")
+        (@arg pretty: --pretty "Echo each console expression in normalized form before its result")
+        (@arg words: --words "Print whole-number results in English words")
+        (@arg compare: --compare +takes_value number_of_values(2) value_names(&["A", "B"]) conflicts_with[expr watch] "Evaluate two expressions and report whether their results are equal")
+        (@arg fixed: --fixed +takes_value "Print results with exactly this many decimal places")
+        (@arg sigfigs: --sigfigs +takes_value conflicts_with[fixed] "Print results rounded to this many significant figures")
+        (@arg cfrac: --cfrac "Print results as continued fractions, such as [3; 4, 12, 4]")
+        (@arg cfrac_terms: --("cfrac-terms") +takes_value default_value("10") "The most terms to print with --cfrac")
+        (@arg cfrac_tolerance: --("cfrac-tolerance") +takes_value default_value("1e-9") "How close to an integer a term must be to end the expansion with --cfrac")
+        (@arg track_stats: --("track-stats") "Keep statistics over console results, shown by :stats")
+        (@arg round_mode: --("round-mode") +takes_value possible_values(&["nearest", "toward-zero", "up", "down"]) requires[round_places] "Round the result of every operation, up meaning toward +inf")
+        (@arg round_places: --("round-places") +takes_value requires[round_mode] "Decimal places to round to with --round-mode")
+        (@arg strict_whitespace: --("strict-whitespace") "Require spaces around binary operators, as in 2 + 3")
+        (@arg auto_close: --("auto-close") "Close any parentheses left open at the end of an expression")
+        (@arg disable: --disable +takes_value "Reject these comma-separated operators and console commands, such as ^,factorize")
+        (@arg explain_precedence: --("explain-precedence-only") conflicts_with[watch compare] "Point out where the expression relies on operator precedence, without evaluating it")
+        (@arg profile: --profile "Report time spent tokenizing and evaluating to stderr")
+        (@arg watch: --watch requires[expr] "Re-evaluate the expression periodically until interrupted")
+        (@arg interval: --interval +takes_value default_value("1000") "Milliseconds between evaluations in watch mode")
     )
     .get_matches();
 
-    if let Some(expr) = matches.value_of("expr") {
-        println!("{}", solve(expr));
-    } else {
-        console();
+    let mut ctx = EvalContext {
+        caret_xor: matches.is_present("caret_xor"),
+        power: match matches.value_of("power") {
+            Some("^") if matches.is_present("caret_xor") => Error::with_description(
+                "--power ^ cannot be used with --caret-xor, which makes ^ mean XOR",
+                ErrorKind::ArgumentConflict,
+            )
+            .exit(),
+            Some("^") => PowerToken::Caret,
+            Some("**") => PowerToken::Stars,
+            _ => PowerToken::Both,
+        },
+        group_input: matches.is_present("group_input"),
+        trace_stack: matches.is_present("trace_stack"),
+        auto_close: matches.is_present("auto_close"),
+        strict_whitespace: matches.is_present("strict_whitespace"),
+        rounding: matches.value_of("round_mode").map(|mode| StepRounding {
+            mode: match mode {
+                "toward-zero" => RoundMode::TowardZero,
+                "up" => RoundMode::Up,
+                "down" => RoundMode::Down,
+                _ => RoundMode::Nearest,
+            },
+            places: {
+                let places = value_t!(matches, "round_places", i32).unwrap_or_else(|e| e.exit());
+                if !(0..=15).contains(&places) {
+                    Error::with_description(
+                        "--round-places must be from 0 to 15",
+                        ErrorKind::InvalidValue,
+                    )
+                    .exit()
+                }
+                places
+            },
+        }),
+        constants: match matches.value_of("constants") {
+            Some("physics") => constants::physics_constants(),
+            _ => &[],
+        },
+        ..EvalContext::default()
+    };
+
+    let profile = matches.is_present("profile");
+    let mut disabled_commands = Vec::new();
+    for name in matches
+        .value_of("disable")
+        .into_iter()
+        .flat_map(|names| names.split(','))
+    {
+        let name = name.trim();
+
+        if TEXT_COMMANDS.contains(&name) {
+            disabled_commands.push(name.to_owned());
+        } else if let Some(op) = Operator::from_keyword(name) {
+            ctx.disabled_operators.push(op);
+        } else if let Some(op) = Operator::from_symbol(name, &ctx)
+            .filter(|op| !matches!(op, Operator::LPar | Operator::RPar))
+        {
+            ctx.disabled_operators.push(op);
+        } else {
+            Error::with_description(
+                &format!("Unknown operator or command '{}' for --disable", name),
+                ErrorKind::InvalidValue,
+            )
+            .exit()
+        }
     }
-}
 
-fn solve(str: &str) -> f64 {
-    let mut output: Vec<f64> = Vec::new();
-    let mut operator_stack: Vec<Operator> = Vec::new();
+    let fmt = FormatOptions {
+        locale: matches.value_of("locale").map(|name| {
+            Locale::from_name(name).unwrap_or_else(|| {
+                let names: Vec<_> = Locale::names().collect();
+                Error::with_description(
+                    &format!(
+                        "Unknown locale '{}', expected one of: {}",
+                        name,
+                        names.join(", ")
+                    ),
+                    ErrorKind::InvalidValue,
+                )
+                .exit()
+            })
+        }),
+        nan_text: matches.value_of("nan_text").map(str::to_owned),
+        inf_text: matches.value_of("inf_text").map(str::to_owned),
+        pretty: matches.is_present("pretty"),
+        words: matches.is_present("words"),
+        fixed: matches
+            .value_of("fixed")
+            .map(|_| value_t!(matches, "fixed", usize).unwrap_or_else(|e| e.exit())),
+        sigfigs: matches.value_of("sigfigs").map(|_| {
+            let figs = value_t!(matches, "sigfigs", usize).unwrap_or_else(|e| e.exit());
+            if figs == 0 {
+                Error::with_description("--sigfigs must be at least 1", ErrorKind::InvalidValue)
+                    .exit()
+            }
+            figs
+        }),
+        cfrac: if matches.is_present("cfrac") {
+            let terms = value_t!(matches, "cfrac_terms", usize).unwrap_or_else(|e| e.exit());
+            if terms == 0 {
+                Error::with_description("--cfrac-terms must be at least 1", ErrorKind::InvalidValue)
+                    .exit()
+            }
 
-    for token in tokenize(str) {
-        match token {
-            Token::Number(n) => output.push(n),
-            Token::Operator(Operator::LPar) => operator_stack.push(Operator::LPar),
-            Token::Operator(Operator::RPar) => {
-                while first_operator_in_stack(&operator_stack) != Some(Operator::LPar) {
-                    assert!(!operator_stack.is_empty(), "Mismatched parentheses");
-                    output.apply(operator_stack.pop().unwrap());
+            let tolerance = value_t!(matches, "cfrac_tolerance", f64).unwrap_or_else(|e| e.exit());
+            if !(tolerance > 0.0 && tolerance.is_finite()) {
+                Error::with_description(
+                    "--cfrac-tolerance must be positive and finite",
+                    ErrorKind::InvalidValue,
+                )
+                .exit()
+            }
+
+            Some(ContinuedFraction { terms, tolerance })
+        } else {
+            None
+        },
+        currency: if matches.is_present("currency") {
+            Some(Currency {
+                symbol: matches.value_of("currency_symbol").unwrap().to_owned(),
+                rounding: match matches.value_of("currency_rounding") {
+                    Some("half-even") => Rounding::HalfEven,
+                    _ => Rounding::HalfUp,
+                },
+            })
+        } else {
+            None
+        },
+    };
+
+    // With no positional expression, CALC_EXPR is evaluated instead, and the
+    // console is only started if that isn't set either.
+    let expr = matches
+        .value_of("expr")
+        .map(str::to_owned)
+        .or_else(|| env::var("CALC_EXPR").ok().filter(|expr| !expr.is_empty()));
+
+    if let Some(mut exprs) = matches.values_of("compare") {
+        let (a, b) = (exprs.next().unwrap(), exprs.next().unwrap());
+        compare(strip_comment(a), strip_comment(b), &ctx, &fmt, profile)
+            .unwrap_or_else(|e| fail(&e));
+    } else if let Some(expr) = expr.as_deref() {
+        if matches.is_present("explain_precedence") {
+            for line in expr.lines().map(strip_comment) {
+                let notes = precedence_notes(line, &ctx).unwrap_or_else(|e| fail(&e.to_string()));
+                for note in notes {
+                    println!("note: {}", note);
                 }
+            }
+        } else if matches.is_present("watch") {
+            let interval = value_t!(matches, "interval", u64).unwrap_or_else(|e| e.exit());
+            watch(
+                strip_comment(expr),
+                &ctx,
+                &fmt,
+                Duration::from_millis(interval),
+                profile,
+            );
+        } else {
+            // Like piped console input, each non-empty line is a separate
+            // expression with its own result.
+            let mut total = Timings::default();
+            let lines: Vec<&str> = expr
+                .lines()
+                .map(strip_comment)
+                .filter(|l| !l.is_empty())
+                .collect();
 
-                assert_eq!(Some(Operator::LPar), operator_stack.pop());
-            }
-            Token::Operator(op) => {
-                while let Some(stack_operator) = first_non_para_in_stack(&operator_stack) {
-                    if stack_operator.precedence() > op.precedence()
-                        || (stack_operator.precedence() == op.precedence()
-                            && op.is_left_associative())
-                    {
-                        output.apply(operator_stack.pop().unwrap());
-                    } else {
-                        break;
+            for line in lines.iter() {
+                let output = match line.split_once('=') {
+                    Some((lhs, rhs)) => check_claim(lhs, rhs, &ctx, &fmt, profile),
+                    None => {
+                        let (value, warnings, timings) = solve_timed(line, &ctx);
+                        print_warnings(&warnings);
+                        if profile {
+                            eprintln!("profile: {}", timings);
+                        }
+                        total.add(timings);
+                        value
+                            .map(|n| format_result(n, &fmt))
+                            .map_err(|e| e.to_string())
                     }
-                }
+                };
+                println!("{}", output.unwrap_or_else(|e| fail(&e)));
+            }
 
-                operator_stack.push(op);
+            if profile && lines.len() > 1 {
+                eprintln!("profile: total {}", total);
             }
         }
+    } else {
+        console(
+            &ctx,
+            &fmt,
+            matches.is_present("track_stats"),
+            disabled_commands,
+            profile,
+        );
     }
+}
 
-    operator_stack.reverse();
-
-    for operator in operator_stack {
-        assert_ne!(Operator::LPar, operator, "Mismatched parentheses");
-        output.apply(operator);
+/// Solves `str`, printing any warnings to stderr, and with `profile` the
+/// time taken by each phase.
+fn solve(str: &str, ctx: &EvalContext, profile: bool) -> Result<f64, String> {
+    let (value, warnings, timings) = solve_timed(str, ctx);
+    print_warnings(&warnings);
+    if profile {
+        eprintln!("profile: {}", timings);
     }
+    value.map_err(|e| e.to_string())
+}
 
-    output[0]
+/// Reports an error outside the console and exits unsuccessfully.
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1)
 }
 
-#[derive(Clone, Copy, Debug)]
-enum Token {
-    Number(f64),
-    Operator(Operator),
+fn print_warnings(warnings: &[Warning]) {
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
 }
 
-impl Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Number(n) => n.fmt(f),
-            Self::Operator(op) => op.fmt(f),
+fn console(
+    ctx: &EvalContext,
+    fmt: &FormatOptions,
+    track_stats: bool,
+    disabled_commands: Vec<String>,
+    profile: bool,
+) {
+    let mut console = Console::new(ctx, fmt, track_stats);
+    console.disabled_commands = disabled_commands;
+    console.profile = profile;
+
+    while let Some(input) = read_input() {
+        match console.respond(&input) {
+            Reply::Nothing => {}
+            Reply::Quit => break,
+            Reply::Output(output) => println!("{}", output),
+            Reply::Error(message) => eprintln!("{}", message),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum Operator {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Mod,
-    Exp,
-    LPar,
-    RPar,
-}
-
-impl Operator {
-    fn from_symbol(s: char) -> Self {
-        match s {
-            '+' => Self::Add,
-            '-' => Self::Sub,
-            '*' => Self::Mul,
-            '/' => Self::Div,
-            '%' => Self::Mod,
-            '^' => Self::Exp,
-            '(' => Self::LPar,
-            ')' => Self::RPar,
-            _ => panic!(),
+/// What the console prints in response to a line of input.
+#[derive(Clone, Debug, PartialEq)]
+enum Reply {
+    Nothing,
+    Quit,
+    /// Printed to stdout.
+    Output(String),
+    /// Printed to stderr.
+    Error(String),
+}
+
+/// The state of a console session, kept from one input to the next.
+struct Console<'a> {
+    ctx: &'a EvalContext,
+    fmt: &'a FormatOptions,
+    /// Solves plain expressions. Tests replace this to simulate a bug.
+    solve: fn(&str, &EvalContext, bool) -> Result<f64, String>,
+    /// Text commands, such as `factorize`, that are rejected.
+    disabled_commands: Vec<String>,
+    /// Reports the time spent tokenizing and evaluating to stderr.
+    profile: bool,
+    previous: Option<String>,
+    stats: Option<RunningStats>,
+    /// The input and message of the most recent failure, cleared by the next
+    /// successful evaluation.
+    last_error: Option<(String, String)>,
+}
+
+impl<'a> Console<'a> {
+    fn new(ctx: &'a EvalContext, fmt: &'a FormatOptions, track_stats: bool) -> Self {
+        Self {
+            ctx,
+            fmt,
+            solve,
+            disabled_commands: Vec::new(),
+            profile: false,
+            previous: None,
+            stats: track_stats.then(RunningStats::default),
+            last_error: None,
+        }
+    }
+
+    fn respond(&mut self, input: &str) -> Reply {
+        let input: &str = strip_comment(input);
+
+        match input {
+            "" => return Reply::Nothing,
+            "q" | "exit" => return Reply::Quit,
+            ":precedence" => {
+                return Reply::Output(precedence_table(self.ctx).trim_end().to_string());
+            }
+            ":stats" => {
+                return match &self.stats {
+                    Some(stats) => Reply::Output(stats.report(self.fmt).trim_end().to_string()),
+                    None => Reply::Error(
+                        "error: statistics are only kept with --track-stats".to_string(),
+                    ),
+                };
+            }
+            ":reset-stats" => {
+                if let Some(stats) = &mut self.stats {
+                    *stats = RunningStats::default();
+                }
+                return Reply::Nothing;
+            }
+            ":error" => {
+                return match &self.last_error {
+                    Some((input, message)) => {
+                        Reply::Error(format!("error in '{}': {}", input, message))
+                    }
+                    None => Reply::Output("no recent error".to_string()),
+                };
+            }
+            _ => {}
+        }
+
+        let expr = match substitute_previous(input, self.previous.as_deref()) {
+            Some(expr) => expr,
+            None => {
+                let message = "_ used with no previous expression".to_string();
+                return self.fail(input.to_string(), message);
+            }
+        };
+
+        // Only plain expressions produce a value; text commands and claims
+        // just produce output.
+        let (ctx, fmt, solve, profile) = (self.ctx, self.fmt, self.solve, self.profile);
+        let output = if let Some((name, arg)) = text_command(&expr) {
+            if self
+                .disabled_commands
+                .iter()
+                .any(|disabled| disabled == name)
+            {
+                let message = format!("'{}' is disabled", name);
+                return self.fail(expr.clone(), message);
+            }
+            catch_panic(|| run_text_command(name, arg, ctx, profile).map(|output| (output, None)))
+        } else if let Some((lhs, rhs)) = expr.split_once('=') {
+            catch_panic(|| check_claim(lhs, rhs, ctx, fmt, profile).map(|output| (output, None)))
+        } else {
+            catch_panic(|| {
+                let n = solve(&expr, ctx, profile)?;
+                if fmt.pretty {
+                    let normalized = normalize(&expr, ctx).map_err(|e| e.to_string())?;
+                    Ok((
+                        format!("{} = {}", normalized, format_result(n, fmt)),
+                        Some(n),
+                    ))
+                } else {
+                    Ok((format_result(n, fmt), Some(n)))
+                }
+            })
+        };
+
+        match output {
+            Ok((output, value)) => {
+                if let Some(n) = value {
+                    self.previous = Some(expr);
+                    if let Some(stats) = &mut self.stats {
+                        stats.push(n);
+                    }
+                }
+                self.last_error = None;
+                Reply::Output(output)
+            }
+            Err(message) => self.fail(expr, message),
         }
     }
 
-    fn precedence(&self) -> usize {
-        match self {
-            Self::Add | Self::Sub => 2,
-            Self::Mul | Self::Div | Self::Mod => 3,
-            Self::Exp => 4,
-            _ => 0,
+    /// Records `message` as the most recent error, for `:error`.
+    fn fail(&mut self, input: String, message: String) -> Reply {
+        let reply = Reply::Error(format!("error: {}", message));
+        self.last_error = Some((input, message));
+        reply
+    }
+}
+
+/// Aggregates over a stream of results, updated one value at a time.
+#[derive(Clone, Debug, Default)]
+struct RunningStats {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    /// The sum of squared differences from the mean (Welford's algorithm).
+    m2: f64,
+}
+
+impl RunningStats {
+    fn push(&mut self, n: f64) {
+        if self.count == 0 {
+            self.min = n;
+            self.max = n;
+        } else {
+            self.min = self.min.min(n);
+            self.max = self.max.max(n);
         }
+
+        self.count += 1;
+        self.sum += n;
+
+        let delta = n - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (n - self.mean);
     }
 
-    fn is_left_associative(&self) -> bool {
-        matches!(
-            self,
-            Self::Add | Self::Sub | Self::Mul | Self::Div | Self::Mod
-        )
+    /// The population standard deviation of the results so far.
+    fn stddev(&self) -> f64 {
+        (self.m2 / self.count as f64).sqrt()
     }
 
-    fn apply(&self, lhs: f64, rhs: f64) -> f64 {
-        match self {
-            Self::Add => lhs + rhs,
-            Self::Sub => lhs - rhs,
-            Self::Mul => lhs * rhs,
-            Self::Div => lhs / rhs,
-            Self::Mod => lhs % rhs,
-            Self::Exp => lhs.powf(rhs),
-            _ => panic!(),
+    fn report(&self, fmt: &FormatOptions) -> String {
+        if self.count == 0 {
+            return "no results yet\n".to_string();
+        }
+
+        let rows = [
+            ("sum", self.sum),
+            ("mean", self.mean),
+            ("min", self.min),
+            ("max", self.max),
+            ("stddev", self.stddev()),
+        ];
+
+        let mut report = format!("{:<8}{}\n", "count", self.count);
+        for (name, value) in rows.iter() {
+            report.push_str(&format!("{:<8}{}\n", name, format_result(*value, fmt)));
         }
+
+        report
     }
 }
 
-impl Display for Operator {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Add => write!(f, "+"),
-            Self::Sub => write!(f, "-"),
-            Self::Mul => write!(f, "*"),
-            Self::Div => write!(f, "/"),
-            Self::Mod => write!(f, "%"),
-            Self::Exp => write!(f, "^"),
-            Self::LPar => write!(f, "("),
-            Self::RPar => write!(f, ")"),
+/// Console commands that print text rather than a number. They're written
+/// like a function call, e.g. `factorize(360)`.
+const TEXT_COMMANDS: &[&str] = &[
+    "factorize",
+    "pi_digits",
+    "e_digits",
+    "hex",
+    "bin",
+    "oct",
+    "dec",
+];
+
+/// Splits a text command into its name and argument, or returns `None` if
+/// `input` isn't one.
+fn text_command(input: &str) -> Option<(&str, &str)> {
+    let (name, arg) = input.strip_suffix(')')?.split_once('(')?;
+    let name = name.trim();
+
+    TEXT_COMMANDS.contains(&name).then_some((name, arg))
+}
+
+fn run_text_command(
+    name: &str,
+    arg: &str,
+    ctx: &EvalContext,
+    profile: bool,
+) -> Result<String, String> {
+    // dec reads a prefixed literal, which isn't valid in an expression.
+    if name == "dec" {
+        return parse_radix_literal(arg.trim()).map(|n| n.to_string());
+    }
+
+    let n = solve(arg, ctx, profile)?;
+
+    match name {
+        "factorize" => factorize(n),
+        "pi_digits" => leading_digits(PI_DIGITS, n),
+        "e_digits" => leading_digits(E_DIGITS, n),
+        "hex" => to_radix(n, 16, "0x"),
+        "bin" => to_radix(n, 2, "0b"),
+        "oct" => to_radix(n, 8, "0o"),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes the integer `n` in `radix` after `prefix`, as in `0xFF`. A
+/// negative number gets a leading `-` rather than a two's complement form.
+fn to_radix(n: f64, radix: u32, prefix: &str) -> Result<String, String> {
+    if n.fract() != 0.0 || n.abs() > MAX_EXACT_INTEGER {
+        return Err("Base conversion requires an integer of magnitude at most 2^53".to_string());
+    }
+
+    let sign = if n < 0.0 { "-" } else { "" };
+    let mut magnitude = n.abs() as u64;
+    let mut digits = Vec::new();
+
+    loop {
+        digits.push(std::char::from_digit((magnitude % radix as u64) as u32, radix).unwrap());
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break;
         }
     }
+
+    let digits: String = digits.iter().rev().collect();
+    Ok(format!("{}{}{}", sign, prefix, digits.to_uppercase()))
 }
 
-fn tokenize(str: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
+/// Parses an integer written with a `0x`, `0b` or `0o` prefix, or in plain
+/// decimal, after an optional `-`.
+fn parse_radix_literal(literal: &str) -> Result<i64, String> {
+    let (sign, digits) = match literal.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, literal),
+    };
 
-    let regex = Regex::new(r"([0-9]+(.[0-9]+)*)|([+\-*/%^\(\)])").unwrap();
+    let prefix = digits.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits) = match prefix.as_deref() {
+        Some("0x") => (16, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        _ => (10, digits),
+    };
+
+    i64::from_str_radix(digits, radix)
+        .map(|n| sign * n)
+        .map_err(|_| format!("Invalid integer literal '{}'", literal))
+}
 
-    for token in regex.captures_iter(str) {
-        if let Some(number) = token.get(1).map(|m| m.as_str()) {
-            let n = f64::from_str(number).unwrap();
-            tokens.push(Token::Number(n));
-        } else if let Some(operator) = token.get(3).map(|m| m.as_str()) {
-            let op = Operator::from_symbol(operator.chars().next().unwrap());
-            tokens.push(Token::Operator(op));
+/// 2^53. Every integer up to this magnitude is exactly representable as an
+/// `f64`.
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// The first 50 digits of pi and e, since an `f64` only holds about 16.
+const PI_DIGITS: &str = "3.1415926535897932384626433832795028841971693993751";
+const E_DIGITS: &str = "2.7182818284590452353602874713526624977572470936999";
+
+/// Returns the first `n` digits of `digits`, truncated rather than rounded.
+fn leading_digits(digits: &str, n: f64) -> Result<String, String> {
+    let max = digits.len() - 1;
+    if n.fract() != 0.0 || n < 1.0 || n > max as f64 {
+        return Err(format!(
+            "The number of digits must be an integer from 1 to {}",
+            max
+        ));
+    }
+
+    Ok(match n as usize {
+        1 => digits[..1].to_owned(),
+        // One more character for the decimal point.
+        n => digits[..=n].to_owned(),
+    })
+}
+
+/// Writes the prime factorization of `n` by trial division, as in
+/// `2^3 * 3^2 * 5`.
+fn factorize(n: f64) -> Result<String, String> {
+    if n.fract() != 0.0 || !(2.0..=MAX_EXACT_INTEGER).contains(&n) {
+        return Err("factorize requires an integer from 2 to 2^53".to_string());
+    }
+
+    let mut n = n as u64;
+    let mut factors = Vec::new();
+    let mut p = 2;
+
+    while p * p <= n {
+        let mut power = 0;
+        while n.is_multiple_of(p) {
+            n /= p;
+            power += 1;
         }
+
+        match power {
+            0 => {}
+            1 => factors.push(p.to_string()),
+            _ => factors.push(format!("{}^{}", p, power)),
+        }
+        p += 1;
+    }
+
+    if n > 1 {
+        factors.push(n.to_string());
     }
 
-    tokens
+    Ok(factors.join(" * "))
 }
 
-fn first_operator_in_stack(stack: &[Operator]) -> Option<Operator> {
-    if stack.is_empty() {
-        return None;
+/// Replaces each standalone `_` in `input` with the previous expression,
+/// parenthesized, so after `3 + 4` the input `_ * 2` is `(3 + 4) * 2`.
+/// Returns `None` if `_` is used but there is no previous expression.
+fn substitute_previous(input: &str, previous: Option<&str>) -> Option<String> {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER.get_or_init(|| Regex::new(r"\b_\b").unwrap());
+
+    if !placeholder.is_match(input) {
+        return Some(input.to_string());
     }
 
-    Some(stack[stack.len() - 1])
+    previous.map(|previous| {
+        let replacement = format!("({})", previous);
+        placeholder
+            .replace_all(input, NoExpand(&replacement))
+            .into_owned()
+    })
 }
 
-fn first_non_para_in_stack(stack: &[Operator]) -> Option<Operator> {
-    first_operator_in_stack(stack).and_then(|op| if op != Operator::LPar { Some(op) } else { None })
+/// Runs an evaluation, catching any panic so that a bug can't end a console
+/// session. Evaluation errors are returned rather than panicking, so any
+/// panic is a bug and is reported as one. The default panic hook is silenced
+/// meanwhile so the message isn't printed twice.
+fn catch_panic<T>(
+    evaluate: impl FnOnce() -> Result<T, String> + panic::UnwindSafe,
+) -> Result<T, String> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(evaluate);
+    panic::set_hook(hook);
+
+    result.unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned());
+
+        Err(match message {
+            Some(message) => format!("internal error, please report: {}", message),
+            None => "internal error, please report".to_string(),
+        })
+    })
 }
 
-trait Apply {
-    fn apply(&mut self, op: Operator);
+/// Removes a trailing `#` comment, and surrounding whitespace, from a line
+/// of input.
+fn strip_comment(line: &str) -> &str {
+    line.split('#').next().unwrap().trim()
 }
 
-impl Apply for Vec<f64> {
-    fn apply(&mut self, op: Operator) {
-        self.pop()
-            .and_then(|x| self.pop().map(|y| self.push(op.apply(y, x))))
-            .unwrap()
+/// Reads the next line for the console, returning `None` once the input is
+/// exhausted (Ctrl-D, or the end of piped input) so the session ends as if
+/// `exit` had been typed. Any other failure to read is reported and exits.
+///
+/// The interactive prompt needs a terminal on both stdin and stderr; in any
+/// other case lines are read directly from stdin.
+fn read_input() -> Option<String> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        let mut line = String::new();
+
+        return match io::stdin().read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(line.trim_end().to_string()),
+            Err(e) => fail(&format!("Failed to read input: {}", e)),
+        };
+    }
+
+    match prompt(&Term::stderr(), "> ") {
+        Ok(input) => input,
+        Err(e) => fail(&format!("Failed to read input: {}", e)),
     }
 }
 
-fn console() {
+/// Reads a line from the terminal key by key, allowing it to be edited with
+/// backspace and the arrow keys. The terminal is in raw mode while a key is
+/// read, so Ctrl-D arrives as a character rather than as end of input; on an
+/// empty line it returns `None`, as it would in a shell.
+fn prompt(term: &Term, prompt: &str) -> io::Result<Option<String>> {
+    term.write_str(prompt)?;
+    term.flush()?;
+
+    let mut chars: Vec<char> = Vec::new();
+    let mut position = 0;
+
     loop {
-        let input: String = Input::new().with_prompt("> ").interact_text().unwrap();
+        match term.read_key()? {
+            Key::Char('\x04') if chars.is_empty() => {
+                term.write_line("")?;
+                return Ok(None);
+            }
+            Key::Char(c) if !c.is_ascii_control() => {
+                chars.insert(position, c);
+                position += 1;
+                let tail: String = chars[position - 1..].iter().collect();
+                term.write_str(&tail)?;
+                term.move_cursor_left(tail.chars().count() - 1)?;
+            }
+            Key::Backspace if position > 0 => {
+                position -= 1;
+                chars.remove(position);
+                term.clear_chars(1)?;
+                let tail: String = chars[position..].iter().collect();
+                if !tail.is_empty() {
+                    term.write_str(&tail)?;
+                    term.move_cursor_left(chars.len() - position)?;
+                }
+            }
+            Key::ArrowLeft if position > 0 => {
+                position -= 1;
+                term.move_cursor_left(1)?;
+            }
+            Key::ArrowRight if position < chars.len() => {
+                position += 1;
+                term.move_cursor_right(1)?;
+            }
+            Key::Enter => {
+                term.write_line("")?;
+                return Ok(Some(chars.into_iter().collect()));
+            }
+            _ => {}
+        }
+        term.flush()?;
+    }
+}
 
-        let input: &str = &input;
+/// Checks a claimed result such as `2 + 2 = 4`, reporting `correct` or the
+/// claimed and actual values. The claim may be any expression.
+fn check_claim(
+    expr: &str,
+    claim: &str,
+    ctx: &EvalContext,
+    fmt: &FormatOptions,
+    profile: bool,
+) -> Result<String, String> {
+    if claim.contains('=') {
+        return Err("Only one = is allowed in a claim".to_string());
+    }
+    let (actual, claimed) = (solve(expr, ctx, profile)?, solve(claim, ctx, profile)?);
 
-        if matches!(input, "q" | "exit") {
-            break;
+    Ok(if approx_eq(actual, claimed) {
+        "correct".to_string()
+    } else {
+        format!(
+            "expected {}, got {}",
+            format_result(claimed, fmt),
+            format_result(actual, fmt)
+        )
+    })
+}
+
+/// Whether two results are equal to within a relative tolerance of 1e-9.
+fn approx_eq(x: f64, y: f64) -> bool {
+    x == y || (x - y).abs() <= 1e-9 * x.abs().max(y.abs())
+}
+
+/// Evaluates two expressions and prints both results, followed by whether
+/// they are equal to within a relative tolerance.
+fn compare(
+    a: &str,
+    b: &str,
+    ctx: &EvalContext,
+    fmt: &FormatOptions,
+    profile: bool,
+) -> Result<(), String> {
+    let (x, y) = (solve(a, ctx, profile)?, solve(b, ctx, profile)?);
+
+    println!("{} = {}", a, format_result(x, fmt));
+    println!("{} = {}", b, format_result(y, fmt));
+
+    if approx_eq(x, y) {
+        println!("equal");
+    } else {
+        println!("not equal");
+    }
+    Ok(())
+}
+
+/// Evaluates `expr` every `interval` and prints each result. This never
+/// returns; the loop is stopped by interrupting the process with Ctrl-C, or
+/// by the expression failing to evaluate.
+fn watch(
+    expr: &str,
+    ctx: &EvalContext,
+    fmt: &FormatOptions,
+    interval: Duration,
+    profile: bool,
+) -> ! {
+    loop {
+        match solve(expr, ctx, profile) {
+            Ok(n) => println!("{}", format_result(n, fmt)),
+            Err(message) => fail(&message),
         }
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn factorize_integers() {
+        assert_eq!(factorize(360.0), Ok("2^3 * 3^2 * 5".to_string()));
+        assert_eq!(factorize(97.0), Ok("97".to_string()));
+        assert_eq!(factorize(2f64.powi(50)), Ok("2^50".to_string()));
+        assert!(factorize(1.5).is_err());
+        assert!(factorize(1.0).is_err());
+        assert!(factorize(2f64.powi(70)).is_err());
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        assert_eq!(
+            catch_panic(|| -> Result<(), String> { panic!("boom") }),
+            Err("internal error, please report: boom".to_string())
+        );
+        assert_eq!(
+            catch_panic(|| -> Result<(), String> { panic!("{} failed", 1) }),
+            Err("internal error, please report: 1 failed".to_string())
+        );
+        assert_eq!(
+            catch_panic(|| -> Result<(), String> { std::panic::panic_any(1) }),
+            Err("internal error, please report".to_string())
+        );
+        assert_eq!(catch_panic(|| Ok(1)), Ok(1));
+    }
+
+    #[test]
+    fn console_survives_a_panic() {
+        let (ctx, fmt) = (EvalContext::default(), FormatOptions::default());
+        let mut console = Console::new(&ctx, &fmt, false);
+        console.solve = |expr, ctx, profile| {
+            if expr == "boom" {
+                panic!("bug while solving {}", expr);
+            }
+            solve(expr, ctx, profile)
+        };
+
+        assert_eq!(console.respond("1 + 1"), Reply::Output("2".to_string()));
+        assert_eq!(
+            console.respond("boom"),
+            Reply::Error(
+                "error: internal error, please report: bug while solving boom".to_string()
+            )
+        );
+        assert_eq!(
+            console.respond(":error"),
+            Reply::Error(
+                "error in 'boom': internal error, please report: bug while solving boom"
+                    .to_string()
+            )
+        );
+        assert_eq!(console.respond("_ * 3"), Reply::Output("6".to_string()));
+        assert_eq!(console.respond("exit"), Reply::Quit);
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        assert_eq!(strip_comment("2 + 3 # this is a comment"), "2 + 3");
+        assert_eq!(strip_comment("# only a comment"), "");
+        assert_eq!(strip_comment("  4 * 5  "), "4 * 5");
+    }
+
+    #[test]
+    fn previous_expression_placeholder() {
+        assert_eq!(
+            substitute_previous("_ * 2", Some("3 + 4")),
+            Some("(3 + 4) * 2".to_string())
+        );
+        assert_eq!(
+            substitute_previous("1 + 1", None),
+            Some("1 + 1".to_string())
+        );
+        assert_eq!(substitute_previous("_ * 2", None), None);
+    }
+
+    #[test]
+    fn claims() {
+        let (ctx, fmt) = (EvalContext::default(), FormatOptions::default());
+        assert_eq!(
+            check_claim("2 + 2 ", " 4", &ctx, &fmt, false),
+            Ok("correct".to_string())
+        );
+        assert_eq!(
+            check_claim("2 + 3", "4", &ctx, &fmt, false),
+            Ok("expected 4, got 5".to_string())
+        );
+        assert_eq!(
+            check_claim("0.1 + 0.2", "0.3", &ctx, &fmt, false),
+            Ok("correct".to_string())
+        );
+        assert!(check_claim("1", "1 = 1", &ctx, &fmt, false).is_err());
+    }
+
+    #[test]
+    fn running_statistics() {
+        let mut stats = RunningStats::default();
+        for n in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter() {
+            stats.push(*n);
+        }
+
+        assert_eq!(stats.count, 8);
+        assert_eq!(stats.sum, 40.0);
+        assert_eq!(stats.mean, 5.0);
+        assert_eq!((stats.min, stats.max), (2.0, 9.0));
+        assert_eq!(stats.stddev(), 2.0);
+        assert!(stats
+            .report(&FormatOptions::default())
+            .starts_with("count   8\nsum     40\n"));
+        assert_eq!(
+            RunningStats::default().report(&FormatOptions::default()),
+            "no results yet\n"
+        );
+    }
+
+    #[test]
+    fn digits_of_constants() {
+        assert_eq!(
+            leading_digits(PI_DIGITS, 10.0),
+            Ok("3.141592653".to_string())
+        );
+        assert_eq!(leading_digits(E_DIGITS, 1.0), Ok("2".to_string()));
+        assert_eq!(leading_digits(PI_DIGITS, 50.0), Ok(PI_DIGITS.to_string()));
+        assert!(leading_digits(PI_DIGITS, 51.0).is_err());
+        assert!(leading_digits(PI_DIGITS, 2.5).is_err());
+    }
+
+    #[test]
+    fn base_conversion() {
+        assert_eq!(to_radix(255.0, 16, "0x"), Ok("0xFF".to_string()));
+        assert_eq!(to_radix(10.0, 2, "0b"), Ok("0b1010".to_string()));
+        assert_eq!(to_radix(-8.0, 8, "0o"), Ok("-0o10".to_string()));
+        assert_eq!(to_radix(0.0, 2, "0b"), Ok("0b0".to_string()));
+        assert!(to_radix(1.5, 16, "0x").is_err());
+
+        assert_eq!(parse_radix_literal("0xFF"), Ok(255));
+        assert_eq!(parse_radix_literal("0b1010"), Ok(10));
+        assert_eq!(parse_radix_literal("-0o10"), Ok(-8));
+        assert_eq!(parse_radix_literal("42"), Ok(42));
+        assert!(parse_radix_literal("0xZZ").is_err());
+    }
+
+    #[test]
+    fn text_commands() {
+        let ctx = EvalContext::default();
+        assert_eq!(text_command("hex(255)"), Some(("hex", "255")));
+        assert_eq!(text_command("sqrt(4)"), None);
+        assert_eq!(
+            run_text_command("hex", "200 + 55", &ctx, false),
+            Ok("0xFF".to_string())
+        );
+        assert_eq!(
+            run_text_command("dec", "0xFF", &ctx, false),
+            Ok("255".to_string())
+        );
+    }
+
+    #[test]
+    fn disabled_commands() {
+        let (ctx, fmt) = (EvalContext::default(), FormatOptions::default());
+        let mut console = Console::new(&ctx, &fmt, false);
+        console.disabled_commands = vec!["factorize".to_string()];
 
-        println!("{}", solve(&input));
+        assert_eq!(
+            console.respond("factorize(360)"),
+            Reply::Error("error: 'factorize' is disabled".to_string())
+        );
+        assert_eq!(
+            console.respond("hex(255)"),
+            Reply::Output("0xFF".to_string())
+        );
     }
 }
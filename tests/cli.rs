@@ -0,0 +1,167 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn calculator() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_calculator"));
+    command.env_remove("CALC_EXPR");
+    command
+}
+
+/// Runs the console with `input` piped to stdin.
+fn console(args: &[&str], input: &str) -> Output {
+    console_bytes(args, input.as_bytes())
+}
+
+fn console_bytes(args: &[&str], input: &[u8]) -> Output {
+    let mut child = calculator()
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn expression_from_the_environment() {
+    let output = calculator().env("CALC_EXPR", "2+2").output().unwrap();
+    assert_eq!(stdout(&output), "4\n");
+
+    let output = calculator()
+        .arg("3 * 3")
+        .env("CALC_EXPR", "2+2")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "9\n");
+}
+
+#[test]
+fn each_line_of_the_argument_is_evaluated() {
+    let output = calculator().arg("2+2\n\n3+3 # six").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "4\n6\n");
+}
+
+#[test]
+fn failures_exit_unsuccessfully() {
+    let output = calculator().arg("2 +").output().unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).starts_with("error: "));
+}
+
+#[test]
+fn compare_two_expressions() {
+    let output = calculator()
+        .args(["--compare", "2+3*4", "(2+3)*4"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "2+3*4 = 14\n(2+3)*4 = 20\nnot equal\n");
+
+    let output = calculator()
+        .args(["--compare", "0.1+0.2", "0.3"])
+        .output()
+        .unwrap();
+    assert!(stdout(&output).ends_with("\nequal\n"));
+}
+
+#[test]
+fn console_ignores_comments() {
+    let output = console(&[], "2 + 3 # a comment\n# only a comment\n");
+    assert_eq!(stdout(&output), "5\n");
+}
+
+#[test]
+fn console_recalls_the_last_error() {
+    let output = console(&[], ":error\n2 +\n:error\n1\n:error\n");
+    assert_eq!(stdout(&output), "no recent error\n1\nno recent error\n");
+
+    let errors = stderr(&output);
+    let lines: Vec<&str> = errors.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[1],
+        format!("error in '2 +': {}", &lines[0]["error: ".len()..])
+    );
+}
+
+#[test]
+fn console_pretty_prints_input() {
+    let output = console(&["--pretty"], "2+  3*4\n(1+2 )*3\n");
+    assert_eq!(stdout(&output), "2 + 3 * 4 = 14\n(1 + 2) * 3 = 9\n");
+}
+
+#[test]
+fn console_tracks_statistics() {
+    let output = console(&["--track-stats"], "2\n4\n:stats\n:reset-stats\n:stats\n");
+    let out = stdout(&output);
+    assert!(out.contains("count   2\nsum     6\nmean    3\n"));
+    assert!(out.ends_with("no results yet\n"));
+}
+
+#[test]
+fn round_places_are_limited() {
+    let output = calculator()
+        .args(["--round-mode", "nearest", "--round-places", "400", "1 + 1"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("--round-places must be from 0 to 15"));
+
+    let output = calculator()
+        .args(["--round-mode", "up", "--round-places", "2", "1 / 3"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "0.34\n");
+}
+
+#[test]
+fn cfrac_tolerance_must_be_positive() {
+    for tolerance in ["0", "-1", "NaN", "inf"].iter() {
+        let output = calculator()
+            .arg("--cfrac")
+            .arg(format!("--cfrac-tolerance={}", tolerance))
+            .arg("2")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(stderr(&output).contains("--cfrac-tolerance must be positive and finite"));
+    }
+}
+
+#[test]
+fn console_reports_unreadable_input() {
+    let output = console_bytes(&[], b"1 + 1\n\xff\n2 + 2\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "2\n");
+    assert!(stderr(&output).starts_with("error: Failed to read input: "));
+}
+
+#[test]
+fn profile_reports_each_expression_and_the_total() {
+    let output = calculator()
+        .args(["--profile", "1 + 1\n2 + 2"])
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "2\n4\n");
+
+    let errors = stderr(&output);
+    let lines: Vec<&str> = errors.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("profile: tokenize "));
+    assert!(lines[1].starts_with("profile: tokenize "));
+    assert!(lines[2].starts_with("profile: total tokenize "));
+
+    let output = calculator().arg("1 + 1").output().unwrap();
+    assert_eq!(stderr(&output), "");
+}